@@ -15,12 +15,57 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use clap::{Parser, ValueEnum, builder::PossibleValue};
-use super::dimensions::{Dimensions, Link, StitchText};
+pub use super::dimensions::{Dimensions, Link, StitchText};
 use super::gauge;
+use super::image_view::{Crop, Rotation};
+use super::mitre::GridSize;
+use super::output::Format;
+use super::page::PageConfig;
+use super::yarn::YarnColor;
+
+impl ValueEnum for Format {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Format::Svg, Format::Png, Format::Pdf]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Format::Svg => PossibleValue::new("svg").help("SVG vector image"),
+            Format::Png => PossibleValue::new("png").help("Rasterized PNG"),
+            Format::Pdf => PossibleValue::new("pdf").help("Vector PDF"),
+        })
+    }
+}
+
+impl ValueEnum for Rotation {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Rotation::Rotate90, Rotation::Rotate180, Rotation::Rotate270]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Rotation::Rotate90 => {
+                PossibleValue::new("90").help("Rotate 90° clockwise")
+            },
+            Rotation::Rotate180 => {
+                PossibleValue::new("180").help("Rotate 180°")
+            },
+            Rotation::Rotate270 => {
+                PossibleValue::new("270").help("Rotate 270° clockwise")
+            },
+        })
+    }
+}
 
 impl ValueEnum for StitchText {
     fn value_variants<'a>() -> &'a [Self] {
-        &[StitchText::None, StitchText::Thread, StitchText::Runs]
+        &[
+            StitchText::None,
+            StitchText::Thread,
+            StitchText::Runs,
+            StitchText::Ruler,
+            StitchText::Symbol,
+        ]
     }
 
     fn to_possible_value(&self) -> Option<PossibleValue> {
@@ -34,6 +79,14 @@ impl ValueEnum for StitchText {
             StitchText::Runs => {
                 PossibleValue::new("runs").help("Counts of runs of same color")
             },
+            StitchText::Symbol => {
+                PossibleValue::new("symbol")
+                    .help("Monochrome symbol, for colorblind-friendly \
+                           or black-and-white printing")
+            },
+            StitchText::Ruler => {
+                PossibleValue::new("ruler").help("Row and stitch ruler")
+            },
         })
     }
 }
@@ -45,6 +98,24 @@ struct Cli {
     input: String,
     #[arg(short, long, value_name = "FILE")]
     output: String,
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<Format>,
+    #[arg(long, value_name = "COUNT",
+          value_parser = clap::value_parser!(u16).range(1..))]
+    colors: Option<u16>,
+    #[arg(short, long, value_name = "COUNT",
+          value_parser = clap::value_parser!(u16).range(1..))]
+    k: Option<u16>,
+    #[arg(long, value_name = "SEED", default_value_t = 0)]
+    seed: u64,
+    #[arg(long, value_name = "STITCHES",
+          value_parser = clap::value_parser!(u16).range(1..))]
+    page_width: Option<u16>,
+    #[arg(long, value_name = "ROWS",
+          value_parser = clap::value_parser!(u16).range(1..))]
+    page_height: Option<u16>,
+    #[arg(long, value_name = "COUNT", default_value_t = 2)]
+    page_overlap: u16,
     #[arg(short, long, value_name = "COUNT", default_value_t = 22,
           value_parser = clap::value_parser!(u16).range(1..))]
     stitches: u16,
@@ -67,6 +138,41 @@ struct Cli {
     #[arg(short, long = "link", value_name = "LINK",
           value_parser = clap::value_parser!(Link))]
     links: Vec<Link>,
+    #[arg(long)]
+    dither: bool,
+    #[arg(long)]
+    serpentine: bool,
+    #[arg(long)]
+    stochastic: bool,
+    #[arg(long, value_name = "COUNT",
+          value_parser = clap::value_parser!(u16).range(1..))]
+    max_colors: Option<u16>,
+    #[arg(long = "yarn", value_name = "COLOR",
+          value_parser = clap::value_parser!(YarnColor))]
+    yarn_palette: Vec<YarnColor>,
+    #[arg(long)]
+    stranded: bool,
+    #[arg(long, value_name = "COUNT",
+          value_parser = clap::value_parser!(u16).range(1..))]
+    max_float: Option<u16>,
+    #[arg(long)]
+    minimize_threads: bool,
+    #[arg(long, value_name = "FRACTION",
+          value_parser = clap::value_parser!(f32))]
+    alpha_threshold: Option<f32>,
+    #[arg(long, value_name = "X,Y,W,H")]
+    crop: Option<Crop>,
+    #[arg(long, value_name = "DEGREES")]
+    rotate: Option<Rotation>,
+    #[arg(long)]
+    flip_h: bool,
+    #[arg(long)]
+    flip_v: bool,
+    #[arg(long, value_name = "COLSxROWS",
+          value_parser = clap::value_parser!(GridSize))]
+    grid: Option<GridSize>,
+    #[arg(long)]
+    grid_checkerboard: bool,
 }
 
 pub struct Files {
@@ -78,6 +184,17 @@ pub struct Config {
     pub dimensions: Dimensions,
     pub mitre: bool,
     pub files: Files,
+    pub format: Format,
+    pub colors: Option<u16>,
+    pub k: Option<u16>,
+    pub seed: u64,
+    pub page: Option<PageConfig>,
+    pub crop: Option<Crop>,
+    pub rotate: Option<Rotation>,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub grid: Option<GridSize>,
+    pub grid_checkerboard: bool,
 }
 
 impl Config {
@@ -85,6 +202,13 @@ impl Config {
         let Cli {
             input,
             output,
+            format,
+            colors,
+            k,
+            seed,
+            page_width,
+            page_height,
+            page_overlap,
             stitches,
             gauge_stitches,
             gauge_rows,
@@ -94,8 +218,34 @@ impl Config {
             stitch_text,
             allow_link_gaps,
             links,
+            dither,
+            serpentine,
+            stochastic,
+            max_colors,
+            yarn_palette,
+            stranded,
+            max_float,
+            minimize_threads,
+            alpha_threshold,
+            crop,
+            rotate,
+            flip_h,
+            flip_v,
+            grid,
+            grid_checkerboard,
         } = Cli::parse();
 
+        let format = format.unwrap_or_else(|| Format::from_path(&output));
+
+        let page = match (page_width, page_height) {
+            (Some(width), Some(height)) => Some(PageConfig {
+                width,
+                height,
+                overlap: page_overlap,
+            }),
+            _ => None,
+        };
+
         Config {
             dimensions: Dimensions {
                 stitches,
@@ -106,9 +256,30 @@ impl Config {
                 allow_link_gaps,
                 links,
                 stitch_text,
+                dither,
+                serpentine,
+                stochastic,
+                seed,
+                max_colors,
+                yarn_palette,
+                stranded,
+                max_float,
+                minimize_threads,
+                alpha_threshold,
             },
             mitre,
             files: Files { input, output },
+            format,
+            colors,
+            k,
+            seed,
+            page,
+            crop,
+            rotate,
+            flip_h,
+            flip_v,
+            grid,
+            grid_checkerboard,
         }
     }
 }
@@ -16,6 +16,7 @@
 
 use std::str::FromStr;
 use std::fmt;
+use super::yarn::YarnColor;
 
 #[derive(Clone, Debug)]
 pub struct Link {
@@ -101,6 +102,7 @@ pub enum StitchText {
     Thread,
     Runs,
     Ruler,
+    Symbol,
 }
 
 #[derive(Clone)]
@@ -113,6 +115,16 @@ pub struct Dimensions {
     pub allow_link_gaps: bool,
     pub links: Vec<Link>,
     pub stitch_text: StitchText,
+    pub dither: bool,
+    pub serpentine: bool,
+    pub stochastic: bool,
+    pub seed: u64,
+    pub max_colors: Option<u16>,
+    pub yarn_palette: Vec<YarnColor>,
+    pub stranded: bool,
+    pub max_float: Option<u16>,
+    pub minimize_threads: bool,
+    pub alpha_threshold: Option<f32>,
 }
 
 impl Default for Dimensions {
@@ -126,6 +138,16 @@ impl Default for Dimensions {
             allow_link_gaps: false,
             links: Vec::new(),
             stitch_text: StitchText::Thread,
+            dither: false,
+            serpentine: false,
+            stochastic: false,
+            seed: 0,
+            max_colors: None,
+            yarn_palette: Vec::new(),
+            stranded: false,
+            max_float: None,
+            minimize_threads: false,
+            alpha_threshold: None,
         }
     }
 }
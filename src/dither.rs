@@ -0,0 +1,54 @@
+// Stichify – A utility to generate intarsia knitting patterns
+// Copyright (C) 2025  Neil Roberts
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::stitch_image::Color;
+
+// Distributes a quantization error to the not-yet-processed Floyd–
+// Steinberg neighbors of a cell: 7/16 to the right, 3/16 to the
+// lower-left, 5/16 below, and 1/16 to the lower-right. Shared between
+// `fabric`'s stitch-based dithering and `sampler`'s sample-based
+// dithering, which otherwise store and look up the diffused error
+// completely differently (a flat array versus a sparse error map
+// keyed by supersample channel).
+pub const WEIGHTS: [(i32, i32, f32); 4] = [
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0),
+    (0, 1, 5.0 / 16.0),
+    (1, 1, 1.0 / 16.0),
+];
+
+// Adds the already-diffused `error` onto `color`, clamping each
+// channel back into range before it gets quantized against a palette.
+pub fn apply_error(color: Color, error: [f32; 3]) -> [f32; 3] {
+    let mut adjusted = [0.0; 3];
+
+    for i in 0..3 {
+        adjusted[i] = (color[i] as f32 + error[i]).clamp(0.0, 255.0);
+    }
+
+    adjusted
+}
+
+// The leftover quantization error between the pre-quantized `adjusted`
+// color and the palette color it was snapped to, ready to be diffused
+// to the neighbors named by `WEIGHTS`.
+pub fn residual(adjusted: [f32; 3], chosen: Color) -> [f32; 3] {
+    [
+        adjusted[0] - chosen[0] as f32,
+        adjusted[1] - chosen[1] as f32,
+        adjusted[2] - chosen[2] as f32,
+    ]
+}
@@ -15,6 +15,9 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::config::{Dimensions, Link};
+use super::dither;
+use super::quantize::Palette;
+use super::yarn::YarnPalette;
 use std::collections::HashMap;
 use std::fmt;
 use std::cmp::Ordering;
@@ -24,16 +27,18 @@ const MAX_STITCH_GAP: u16 = 1;
 
 pub type Color = [u8; 3];
 
-pub trait Image {
-    fn width(&self) -> u32;
-    fn height(&self) -> u32;
-    fn get_pixel(&self, x: u32, y: u32) -> Option<Color>;
-}
+// `fabric::Image` is the same trait as `stitch_image::Image`, not a
+// separate lookalike: every `Image` implementation in the crate
+// (`MitreImage`, `MitreGridImage`, the various view/quantize/k-means
+// wrappers, ...) needs to satisfy `Fabric::new`'s bound without a
+// redundant second `impl` block.
+pub use super::stitch_image::Image;
 
 #[derive(Clone, Debug)]
 pub struct Stitch {
     pub color: Color,
     pub thread: u16,
+    pub region: u32,
 }
 
 #[derive(Debug)]
@@ -43,6 +48,34 @@ pub struct Thread {
     pub id: u16,
     pub color: Color,
     pub stitch_count: u32,
+    pub yarn_name: Option<String>,
+}
+
+// A run of consecutive stitches in a row where `color`'s strand is
+// carried behind the fabric without being worked, because the same
+// color is worked again later in the row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Float {
+    pub y: u16,
+    pub start_x: u16,
+    pub length: u16,
+    pub color: Color,
+    pub exceeds_max: bool,
+}
+
+// A 4-connected group of stitches that all share the same color, used
+// to let `find_thread` reuse a single bobbin for a whole shape even
+// when the serpentine traversal visits it in several disconnected
+// runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub id: u32,
+    pub color: Color,
+    pub min_x: u16,
+    pub min_y: u16,
+    pub max_x: u16,
+    pub max_y: u16,
+    pub stitch_count: u32,
 }
 
 #[derive(Debug)]
@@ -51,6 +84,9 @@ pub struct Fabric {
     n_stitches: u16,
     n_rows: u16,
     threads: Vec<Thread>,
+    strands: Vec<Float>,
+    regions: Vec<Region>,
+    region_threads: HashMap<u32, u16>,
 }
 
 #[derive(Debug)]
@@ -101,6 +137,205 @@ fn most_popular_color<I: Image>(
     colors.keys().max_by_key(|&color| colors[color]).unwrap().clone()
 }
 
+// Builds a median-cut palette of at most `max_colors` entries from the
+// colors already sampled onto the stitches.
+fn build_max_colors_palette(
+    stitches: &[Option<Stitch>],
+    max_colors: u16,
+) -> Palette {
+    let points = stitches.iter()
+        .filter_map(|stitch| stitch.as_ref().map(|stitch| stitch.color))
+        .collect();
+
+    Palette::from_points(points, max_colors as usize)
+}
+
+// Snaps every stitch's color using `nearest`, with no error diffusion.
+fn snap_stitches(
+    stitches: &mut [Option<Stitch>],
+    nearest: impl Fn(Color) -> Color,
+) {
+    for stitch in stitches.iter_mut().flatten() {
+        stitch.color = nearest(stitch.color);
+    }
+}
+
+// Snaps every stitch's color using `nearest`, diffusing the leftover
+// quantization error to the not-yet-processed neighbors so a limited
+// palette doesn't band smooth gradients.
+fn dither_stitches(
+    stitches: &mut [Option<Stitch>],
+    n_stitches: u16,
+    nearest: impl Fn(Color) -> Color,
+) {
+    let n_stitches = n_stitches as i64;
+    let n_rows = stitches.len() as i64 / n_stitches.max(1);
+    let mut errors = vec![[0.0f32; 3]; stitches.len()];
+
+    for y in 0..n_rows {
+        for x in 0..n_stitches {
+            let pos = (y * n_stitches + x) as usize;
+
+            let Some(stitch) = stitches[pos].as_mut() else { continue };
+
+            let adjusted = dither::apply_error(stitch.color, errors[pos]);
+            let sampled = adjusted.map(|component| component.round() as u8);
+            let chosen = nearest(sampled);
+
+            stitch.color = chosen;
+
+            let error = dither::residual(adjusted, chosen);
+
+            for &(dx, dy, weight) in &dither::WEIGHTS {
+                let nx = x + dx as i64;
+                let ny = y + dy as i64;
+
+                if nx < 0 || nx >= n_stitches || ny >= n_rows {
+                    continue;
+                }
+
+                let neighbor_pos = (ny * n_stitches + nx) as usize;
+
+                for i in 0..3 {
+                    errors[neighbor_pos][i] += error[i] * weight;
+                }
+            }
+        }
+    }
+}
+
+// Computes the stranded-colorwork floats for every row: for each
+// color worked more than once in a row, every gap between two
+// consecutive stitches of that color is a span where its strand is
+// carried behind the fabric rather than worked.
+fn calculate_strands(
+    stitches: &[Option<Stitch>],
+    n_stitches: u16,
+    n_rows: u16,
+    max_float: Option<u16>,
+) -> Vec<Float> {
+    let mut strands = Vec::new();
+
+    for y in 0..n_rows {
+        let row = &stitches[(y * n_stitches) as usize
+                             ..((y + 1) * n_stitches) as usize];
+
+        let mut positions = HashMap::<Color, Vec<u16>>::new();
+
+        for (x, stitch) in row.iter().enumerate() {
+            if let Some(stitch) = stitch {
+                positions.entry(stitch.color).or_default().push(x as u16);
+            }
+        }
+
+        for (color, xs) in positions {
+            for pair in xs.windows(2) {
+                let length = pair[1] - pair[0] - 1;
+
+                if length == 0 {
+                    continue;
+                }
+
+                strands.push(Float {
+                    y,
+                    start_x: pair[0] + 1,
+                    length,
+                    color,
+                    exceeds_max: max_float.is_some_and(|max| length > max),
+                });
+            }
+        }
+    }
+
+    strands.sort_unstable_by_key(|float| (float.y, float.start_x, float.color));
+
+    strands
+}
+
+// Groups the stitches into 4-connected regions of matching color,
+// writing the region id onto each stitch and returning the bounding
+// box and stitch count of every region.
+fn label_regions(
+    stitches: &mut [Option<Stitch>],
+    n_stitches: u16,
+    n_rows: u16,
+) -> Vec<Region> {
+    let mut visited = vec![false; stitches.len()];
+    let mut regions = Vec::new();
+    let mut stack = Vec::new();
+
+    for start_y in 0..n_rows {
+        for start_x in 0..n_stitches {
+            let start_pos = (start_y * n_stitches + start_x) as usize;
+
+            if visited[start_pos] || stitches[start_pos].is_none() {
+                continue;
+            }
+
+            let color = stitches[start_pos].as_ref().unwrap().color;
+            let id = regions.len() as u32;
+
+            let mut region = Region {
+                id,
+                color,
+                min_x: start_x,
+                min_y: start_y,
+                max_x: start_x,
+                max_y: start_y,
+                stitch_count: 0,
+            };
+
+            visited[start_pos] = true;
+            stack.push((start_x, start_y));
+
+            while let Some((x, y)) = stack.pop() {
+                let pos = (y * n_stitches + x) as usize;
+
+                stitches[pos].as_mut().unwrap().region = id;
+                region.stitch_count += 1;
+                region.min_x = region.min_x.min(x);
+                region.min_y = region.min_y.min(y);
+                region.max_x = region.max_x.max(x);
+                region.max_y = region.max_y.max(y);
+
+                let mut neighbors = Vec::with_capacity(4);
+
+                if x > 0 {
+                    neighbors.push((x - 1, y));
+                }
+                if x + 1 < n_stitches {
+                    neighbors.push((x + 1, y));
+                }
+                if y > 0 {
+                    neighbors.push((x, y - 1));
+                }
+                if y + 1 < n_rows {
+                    neighbors.push((x, y + 1));
+                }
+
+                for (nx, ny) in neighbors {
+                    let neighbor_pos = (ny * n_stitches + nx) as usize;
+
+                    if visited[neighbor_pos] {
+                        continue;
+                    }
+
+                    if stitches[neighbor_pos].as_ref()
+                        .is_some_and(|stitch| stitch.color == color)
+                    {
+                        visited[neighbor_pos] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+
+            regions.push(region);
+        }
+    }
+
+    regions
+}
+
 impl Fabric {
     pub fn new<I: Image>(
         image: &I,
@@ -149,6 +384,7 @@ impl Fabric {
                 row[x as usize] = color.map(|color| Stitch {
                     color,
                     thread: 0,
+                    region: 0,
                 });
             }
 
@@ -159,15 +395,70 @@ impl Fabric {
             }
         }
 
+        let yarn_palette = if dimensions.yarn_palette.is_empty() {
+            None
+        } else {
+            Some(YarnPalette::new(dimensions.yarn_palette.clone()))
+        };
+
+        // The yarn palette, being an explicit set of real colors,
+        // takes priority over the median-cut palette if both are
+        // given. Dithering is only meaningful once a limited palette
+        // is in play, so it piggybacks on whichever one is active.
+        match (&yarn_palette, dimensions.max_colors) {
+            (Some(palette), _) => {
+                let nearest = |color| palette.nearest(color).color;
+
+                if dimensions.dither {
+                    dither_stitches(&mut stitches, dimensions.stitches, nearest);
+                } else {
+                    snap_stitches(&mut stitches, nearest);
+                }
+            },
+            (None, Some(max_colors)) => {
+                let palette = build_max_colors_palette(&stitches, max_colors);
+                let nearest = |color| palette.nearest(color);
+
+                if dimensions.dither {
+                    dither_stitches(&mut stitches, dimensions.stitches, nearest);
+                } else {
+                    snap_stitches(&mut stitches, nearest);
+                }
+            },
+            (None, None) => {},
+        }
+
         let mut fabric = Fabric {
             stitches,
             n_stitches: dimensions.stitches,
             n_rows,
             threads: Vec::new(),
+            strands: Vec::new(),
+            regions: Vec::new(),
+            region_threads: HashMap::new(),
         };
 
-        let link_map = fabric.links_to_hash(dimensions)?;
-        fabric.calculate_threads(&link_map)?;
+        if dimensions.stranded {
+            fabric.strands = calculate_strands(
+                &fabric.stitches,
+                fabric.n_stitches,
+                fabric.n_rows,
+                dimensions.max_float,
+            );
+        } else {
+            fabric.regions = label_regions(
+                &mut fabric.stitches,
+                fabric.n_stitches,
+                fabric.n_rows,
+            );
+
+            let link_map = fabric.links_to_hash(dimensions)?;
+            fabric.calculate_threads(
+                &link_map,
+                yarn_palette.as_ref(),
+                dimensions.minimize_threads,
+            )?;
+        }
 
         Ok(fabric)
     }
@@ -228,6 +519,8 @@ impl Fabric {
     fn calculate_threads(
         &mut self,
         link_map: &HashMap<(u16, u16), (u16, u16)>,
+        yarn_palette: Option<&YarnPalette>,
+        minimize_threads: bool,
     ) -> Result<(), Error> {
         for y in (0..self.n_rows).rev() {
             for mut x in 0..self.n_stitches {
@@ -238,11 +531,15 @@ impl Fabric {
                 let stitch_pos = (x + y * self.n_stitches) as usize;
 
                 if let Some(stitch) = self.stitches[stitch_pos].as_ref() {
+                    let region = stitch.region;
                     let thread = self.find_thread(
                         link_map,
                         stitch.color.clone(),
                         x,
-                        y
+                        y,
+                        region,
+                        minimize_threads,
+                        yarn_palette,
                     )?;
 
                     thread.stitch_count += 1;
@@ -306,15 +603,30 @@ impl Fabric {
         None
     }
 
+    // Looks up the thread that was last used for `region`, if any is
+    // still tracked, so a shape visited in several disconnected
+    // serpentine runs can still share a single bobbin.
+    fn find_region_thread(&self, region: u32) -> Option<usize> {
+        let thread_id = *self.region_threads.get(&region)?;
+
+        self.threads.iter().position(|thread| thread.id == thread_id)
+    }
+
     fn find_thread(
         &mut self,
         link_map: &HashMap<(u16, u16), (u16, u16)>,
         color: Color,
         x: u16,
         y: u16,
+        region: u32,
+        minimize_threads: bool,
+        yarn_palette: Option<&YarnPalette>,
     ) -> Result<&mut Thread, Error> {
         if let Some(thread_index) =
             self.find_thread_in_links(link_map, x, y)?
+            .or_else(|| {
+                minimize_threads.then(|| self.find_region_thread(region)).flatten()
+            })
             .or_else(|| self.find_neighboring_thread(color, x, y))
         {
             let mut thread = self.threads.remove(thread_index);
@@ -323,6 +635,8 @@ impl Fabric {
             self.threads.push(thread);
         } else {
             let id = self.threads.len() as u16;
+            let yarn_name = yarn_palette
+                .and_then(|palette| palette.nearest(color).name.clone());
 
             self.threads.push(Thread {
                 x,
@@ -330,10 +644,17 @@ impl Fabric {
                 id,
                 color: color.clone(),
                 stitch_count: 0,
+                yarn_name,
             });
         }
 
-        return Ok(self.threads.last_mut().unwrap());
+        let thread = self.threads.last_mut().unwrap();
+
+        if minimize_threads {
+            self.region_threads.insert(region, thread.id);
+        }
+
+        return Ok(thread);
     }
 
     fn compare_position_thread_order(
@@ -359,6 +680,14 @@ impl Fabric {
         &self.threads
     }
 
+    pub fn strands(&self) -> &[Float] {
+        &self.strands
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
     pub fn stitches(&self) -> &[Option<Stitch>] {
         &self.stitches
     }
@@ -375,6 +704,7 @@ impl Fabric {
 #[cfg(test)]
 mod test {
     use super::*;
+    use super::super::yarn::YarnColor;
 
     const FAKE_IMAGE_DATA: &'static [u8] =
         b"##  ##\
@@ -435,8 +765,8 @@ mod test {
         let image = FakeImage::default();
         let mut dimensions = Dimensions::default();
 
-        dimensions.gauge_stitches = 1;
-        dimensions.gauge_rows = 1;
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
         dimensions.stitches = image.width() as u16;
 
         let fabric = Fabric::new(&image, &dimensions).unwrap();
@@ -515,8 +845,8 @@ mod test {
         let image = FakeImage::default();
         let mut dimensions = Dimensions::default();
 
-        dimensions.gauge_stitches = 1;
-        dimensions.gauge_rows = 1;
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
         dimensions.stitches = image.width() as u16;
 
         let fabric = Fabric::new(&image, &dimensions).unwrap();
@@ -555,8 +885,8 @@ mod test {
         let image = FakeImage::default();
         let mut dimensions = Dimensions::default();
 
-        dimensions.gauge_stitches = 1;
-        dimensions.gauge_rows = 1;
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
         dimensions.stitches = image.width() as u16;
         dimensions.links.push(Link { source: (1, 1), dest: (2, 1) });
 
@@ -568,8 +898,8 @@ mod test {
         let image = FakeImage::default();
         let mut dimensions = Dimensions::default();
 
-        dimensions.gauge_stitches = 1;
-        dimensions.gauge_rows = 1;
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
         dimensions.stitches = image.width() as u16;
         dimensions.links.push(Link { source: (5, 1), dest: (2, 1) });
 
@@ -583,14 +913,247 @@ mod test {
         Fabric::new(&image, &dimensions).unwrap();
     }
 
+    #[test]
+    fn regions_group_connected_same_color_stitches() {
+        let image = FakeImage::default();
+        let mut dimensions = Dimensions::default();
+
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
+        dimensions.stitches = image.width() as u16;
+
+        let fabric = Fabric::new(&image, &dimensions).unwrap();
+
+        // All of the black stitches are 4-connected into one big
+        // region, even though the serpentine traversal used to split
+        // them into several threads, plus four separate isolated
+        // white regions.
+        assert_eq!(fabric.regions().len(), 5);
+
+        let black_region = fabric.regions().iter()
+            .find(|region| region.color == [0, 0, 0])
+            .unwrap();
+
+        assert_eq!(black_region.stitch_count, 24);
+        assert_eq!(black_region.min_x, 0);
+        assert_eq!(black_region.min_y, 0);
+        assert_eq!(black_region.max_x, 5);
+        assert_eq!(black_region.max_y, 5);
+    }
+
+    #[test]
+    fn minimize_threads_reuses_a_thread_across_a_region() {
+        let image = FakeImage::default();
+        let mut dimensions = Dimensions::default();
+
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
+        dimensions.stitches = image.width() as u16;
+
+        let without_minimizing = Fabric::new(&image, &dimensions).unwrap();
+        assert_eq!(without_minimizing.threads().len(), 7);
+
+        dimensions.minimize_threads = true;
+
+        let fabric = Fabric::new(&image, &dimensions).unwrap();
+
+        assert_eq!(fabric.threads().len(), 5);
+
+        let black_thread = fabric.threads().iter()
+            .find(|thread| thread.color == [0, 0, 0])
+            .unwrap();
+
+        assert_eq!(black_thread.stitch_count, 24);
+    }
+
+    struct GradientImage;
+
+    impl Image for GradientImage {
+        fn width(&self) -> u32 {
+            4
+        }
+
+        fn height(&self) -> u32 {
+            1
+        }
+
+        fn get_pixel(&self, x: u32, _y: u32) -> Option<Color> {
+            Some([x as u8 * 80, 0, 0])
+        }
+    }
+
+    #[test]
+    fn max_colors_quantizes_the_fabric() {
+        let image = GradientImage;
+        let mut dimensions = Dimensions::default();
+
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
+        dimensions.stitches = image.width() as u16;
+        dimensions.max_colors = Some(2);
+
+        let fabric = Fabric::new(&image, &dimensions).unwrap();
+
+        let mut colors = fabric.stitches().iter()
+            .map(|stitch| stitch.as_ref().unwrap().color)
+            .collect::<Vec<_>>();
+        colors.sort_unstable();
+        colors.dedup();
+
+        assert_eq!(colors, vec![[40, 0, 0], [200, 0, 0]]);
+    }
+
+    #[test]
+    fn yarn_palette_snaps_threads_to_named_colors() {
+        let image = FakeImage::default();
+        let mut dimensions = Dimensions::default();
+
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
+        dimensions.stitches = image.width() as u16;
+        dimensions.yarn_palette = vec![
+            YarnColor {
+                color: [10, 10, 10],
+                name: Some("Charcoal".to_string()),
+            },
+            YarnColor {
+                color: [240, 240, 240],
+                name: Some("Cream".to_string()),
+            },
+        ];
+
+        let fabric = Fabric::new(&image, &dimensions).unwrap();
+
+        for stitch in fabric.stitches().iter().flatten() {
+            assert!(
+                stitch.color == [10, 10, 10] || stitch.color == [240, 240, 240]
+            );
+        }
+
+        let names = fabric.threads().iter()
+            .map(|thread| thread.yarn_name.clone())
+            .collect::<Vec<_>>();
+
+        assert!(names.contains(&Some("Charcoal".to_string())));
+        assert!(names.contains(&Some("Cream".to_string())));
+    }
+
+    struct DitherGradientImage;
+
+    impl Image for DitherGradientImage {
+        fn width(&self) -> u32 {
+            4
+        }
+
+        fn height(&self) -> u32 {
+            1
+        }
+
+        fn get_pixel(&self, x: u32, _y: u32) -> Option<Color> {
+            const VALUES: [u8; 4] = [0, 150, 150, 150];
+
+            Some([VALUES[x as usize], 0, 0])
+        }
+    }
+
+    #[test]
+    fn dither_diffuses_error_to_later_stitches() {
+        let image = DitherGradientImage;
+        let mut dimensions = Dimensions::default();
+
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
+        dimensions.stitches = image.width() as u16;
+        dimensions.yarn_palette = vec![
+            YarnColor { color: [0, 0, 0], name: None },
+            YarnColor { color: [255, 0, 0], name: None },
+        ];
+        dimensions.dither = true;
+
+        let fabric = Fabric::new(&image, &dimensions).unwrap();
+
+        let colors = fabric.stitches().iter()
+            .map(|stitch| stitch.as_ref().unwrap().color)
+            .collect::<Vec<_>>();
+
+        // Without dithering, every stitch but the first would snap to
+        // [255, 0, 0]. With the diffused error, the third stitch tips
+        // back over to [0, 0, 0].
+        assert_eq!(
+            colors,
+            vec![[0, 0, 0], [255, 0, 0], [0, 0, 0], [255, 0, 0]],
+        );
+    }
+
+    struct StrandImage;
+
+    impl Image for StrandImage {
+        fn width(&self) -> u32 {
+            5
+        }
+
+        fn height(&self) -> u32 {
+            1
+        }
+
+        fn get_pixel(&self, x: u32, _y: u32) -> Option<Color> {
+            match x {
+                0 | 2 | 4 => Some([255, 0, 0]),
+                _ => Some([0, 255, 0]),
+            }
+        }
+    }
+
+    #[test]
+    fn stranded_mode_computes_floats() {
+        let image = StrandImage;
+        let mut dimensions = Dimensions::default();
+
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
+        dimensions.stitches = image.width() as u16;
+        dimensions.stranded = true;
+        dimensions.max_float = Some(0);
+
+        let fabric = Fabric::new(&image, &dimensions).unwrap();
+
+        assert!(fabric.threads().is_empty());
+        assert_eq!(
+            fabric.strands().to_vec(),
+            vec![
+                Float {
+                    y: 0,
+                    start_x: 1,
+                    length: 1,
+                    color: [255, 0, 0],
+                    exceeds_max: true,
+                },
+                Float {
+                    y: 0,
+                    start_x: 2,
+                    length: 1,
+                    color: [0, 255, 0],
+                    exceeds_max: true,
+                },
+                Float {
+                    y: 0,
+                    start_x: 3,
+                    length: 1,
+                    color: [255, 0, 0],
+                    exceeds_max: true,
+                },
+            ],
+        );
+    }
+
     #[test]
     fn link_to_missing_stitch() {
         const IMAGE_DATA: &'static [u8] = b"x  x";
         let image = FakeImage::new(IMAGE_DATA, 4);
         let mut dimensions = Dimensions::default();
 
-        dimensions.gauge_stitches = 1;
-        dimensions.gauge_rows = 1;
+        dimensions.gauge_stitches = 1.0;
+        dimensions.gauge_rows = 1.0;
         dimensions.stitches = image.width() as u16;
 
         dimensions.links.push(Link { source: (3, 1), dest: (2, 1) });
@@ -17,17 +17,21 @@
 use super::fabric::{Fabric, Color};
 use simple_xml_builder::XMLElement;
 use super::config::Dimensions;
+use super::dimensions::StitchText;
+use super::page::Page;
 use std::fmt::Write;
 use std::fmt;
 
 const BOX_WIDTH: f32 = 20.0;
 const LINE_WIDTH: f32 = BOX_WIDTH / 6.0;
+const N_SYMBOLS: usize = 6;
 
 struct SvgGenerator<'a> {
     box_width: f32,
     box_height: f32,
     fabric: &'a Fabric,
     dimensions: &'a Dimensions,
+    window: Page,
 }
 
 impl<'a> SvgGenerator<'a> {
@@ -36,18 +40,23 @@ impl<'a> SvgGenerator<'a> {
         x: u16,
         y: u16,
         color: Color,
+        filled: bool,
     ) -> XMLElement {
         let mut path = XMLElement::new("path");
 
-        path.add_attribute(
-            "fill",
-            format!(
-                "rgb({}%, {}%, {}%)",
-                color[0] as f32 * 100.0 / 255.0,
-                color[1] as f32 * 100.0 / 255.0,
-                color[2] as f32 * 100.0 / 255.0,
-            ),
-        );
+        if filled {
+            path.add_attribute(
+                "fill",
+                format!(
+                    "rgb({}%, {}%, {}%)",
+                    color[0] as f32 * 100.0 / 255.0,
+                    color[1] as f32 * 100.0 / 255.0,
+                    color[2] as f32 * 100.0 / 255.0,
+                ),
+            );
+        } else {
+            path.add_attribute("fill", "white");
+        }
 
         path.add_attribute(
             "d",
@@ -64,16 +73,33 @@ impl<'a> SvgGenerator<'a> {
         path
     }
 
+    fn stitch_at(&self, x: u16, y: u16) -> Option<&super::fabric::Stitch> {
+        self.fabric.stitches()[
+            (x + y * self.fabric.n_stitches()) as usize
+        ].as_ref()
+    }
+
     fn generate_boxes(&self) -> XMLElement {
         let mut group = XMLElement::new("g");
 
         group.add_attribute("id", "boxes");
 
-        for (stitch_num, stitch) in self.fabric.stitches().iter().enumerate() {
-            let x = stitch_num as u16 % self.fabric.n_stitches();
-            let y = stitch_num as u16 / self.fabric.n_stitches();
-
-            group.add_child(self.generate_box(x, y, stitch.color));
+        let filled = self.dimensions.stitch_text != StitchText::Symbol;
+
+        for y in self.window.start_y..self.window.start_y + self.window.height
+        {
+            for x in self.window.start_x
+                ..self.window.start_x + self.window.width
+            {
+                let Some(stitch) = self.stitch_at(x, y) else { continue };
+
+                group.add_child(self.generate_box(
+                    x - self.window.start_x,
+                    y - self.window.start_y,
+                    stitch.color,
+                    filled,
+                ));
+            }
         }
 
         group
@@ -121,10 +147,9 @@ impl<'a> SvgGenerator<'a> {
     }
 
     fn generate_grid(&self) -> XMLElement {
-        let fabric = self.fabric;
         let mut path = self.generate_grid_no_id(
-            fabric.n_stitches(),
-            fabric.n_rows()
+            self.window.width,
+            self.window.height,
         );
 
         path.add_attribute("id", "grid");
@@ -159,13 +184,14 @@ impl<'a> SvgGenerator<'a> {
 
         self.set_text_appearance(&mut group);
 
-        for y in 0..self.fabric.n_rows() {
+        for y in self.window.start_y..self.window.start_y + self.window.height
+        {
             let mut text = XMLElement::new("text");
 
             self.set_text_position(
                 &mut text,
-                self.fabric.n_stitches() as f32 * self.box_width,
-                y as f32 * self.box_height,
+                self.window.width as f32 * self.box_width,
+                (y - self.window.start_y) as f32 * self.box_height,
             );
 
             text.add_text(self.fabric.n_rows() - y);
@@ -173,13 +199,15 @@ impl<'a> SvgGenerator<'a> {
             group.add_child(text);
         }
 
-        for x in 0..self.fabric.n_stitches() {
+        for x in self.window.start_x
+            ..self.window.start_x + self.window.width
+        {
             let mut text = XMLElement::new("text");
 
             self.set_text_position(
                 &mut text,
-                x as f32 * self.box_width,
-                self.fabric.n_rows() as f32 * self.box_height,
+                (x - self.window.start_x) as f32 * self.box_width,
+                self.window.height as f32 * self.box_height,
             );
 
             text.add_text(self.fabric.n_stitches() - x);
@@ -190,12 +218,45 @@ impl<'a> SvgGenerator<'a> {
         group
     }
 
+    // A small footer identifying which page this is within the
+    // overall grid of pages, so printed sheets can be reassembled
+    // in the right order.
+    fn generate_page_legend(
+        &self,
+        page_index: usize,
+        n_pages: usize,
+    ) -> XMLElement {
+        let mut text = XMLElement::new("text");
+
+        self.set_text_appearance(&mut text);
+        text.add_attribute("x", 0.0);
+        self.set_text_y(
+            &mut text,
+            (self.window.height + 1) as f32 * self.box_height,
+        );
+
+        text.add_text(format!(
+            "Page {} of {} (column {}, row {})",
+            page_index + 1,
+            n_pages,
+            self.window.page_x + 1,
+            self.window.page_y + 1,
+        ));
+
+        text
+    }
+
+    // `background` is the actual fill color behind the glyph, not
+    // necessarily the stitch's own color: `generate_boxes` leaves
+    // every box white in `StitchText::Symbol` mode, so the contrast
+    // check there must be done against white, or a dark stitch would
+    // get a white-on-white glyph that's invisible.
     fn generate_box_thread_text(
         &self,
         thread: u16,
         x: f32,
         y: f32,
-        color: Color,
+        background: Color,
     ) -> XMLElement {
         let mut element = XMLElement::new("use");
 
@@ -206,7 +267,7 @@ impl<'a> SvgGenerator<'a> {
         element.add_attribute("x", x);
         element.add_attribute("y", y);
 
-        if color.iter().map(|&x| x as u16).sum::<u16>() < 384 {
+        if background.iter().map(|&x| x as u16).sum::<u16>() < 384 {
             element.add_attribute("fill", "rgb(100%, 100%, 100%)");
         }
 
@@ -218,16 +279,28 @@ impl<'a> SvgGenerator<'a> {
 
         group.add_attribute("id", "box-threads");
 
-        for (stitch_num, stitch) in self.fabric.stitches().iter().enumerate() {
-            let x = stitch_num as u16 % self.fabric.n_stitches();
-            let y = stitch_num as u16 / self.fabric.n_stitches();
-
-            group.add_child(self.generate_box_thread_text(
-                stitch.thread,
-                x as f32 * self.box_width,
-                y as f32 * self.box_height,
-                stitch.color,
-            ));
+        let filled = self.dimensions.stitch_text != StitchText::Symbol;
+
+        for y in self.window.start_y..self.window.start_y + self.window.height
+        {
+            for x in self.window.start_x
+                ..self.window.start_x + self.window.width
+            {
+                let Some(stitch) = self.stitch_at(x, y) else { continue };
+
+                let background = if filled {
+                    stitch.color
+                } else {
+                    [255, 255, 255]
+                };
+
+                group.add_child(self.generate_box_thread_text(
+                    stitch.thread,
+                    (x - self.window.start_x) as f32 * self.box_width,
+                    (y - self.window.start_y) as f32 * self.box_height,
+                    background,
+                ));
+            }
         }
 
         group
@@ -243,7 +316,7 @@ impl<'a> SvgGenerator<'a> {
             format!(
                 "translate({} {})",
                 self.box_width,
-                self.box_height * (self.fabric.n_rows() + 2) as f32,
+                self.box_height * (self.window.height + 2) as f32,
             ),
         );
 
@@ -256,6 +329,7 @@ impl<'a> SvgGenerator<'a> {
                 0,
                 y as u16,
                 thread.color,
+                true,
             ));
 
             group.add_child(self.generate_box_thread_text(
@@ -269,9 +343,12 @@ impl<'a> SvgGenerator<'a> {
             count_text.add_attribute("x", self.box_width as f32 * 1.5);
             self.set_text_y(&mut count_text, y as f32 * self.box_height);
 
-            count_text.add_text(
-                stitch_count_text(&self.dimensions, thread.stitch_count)
-            );
+            let count = stitch_count_text(&self.dimensions, thread.stitch_count);
+
+            count_text.add_text(match &thread.yarn_name {
+                Some(yarn_name) => format!("{} {}", count, yarn_name),
+                None => count,
+            });
             counts.add_child(count_text);
         }
 
@@ -285,33 +362,155 @@ impl<'a> SvgGenerator<'a> {
         group
     }
 
-    fn generate_defs(&self) -> XMLElement {
-        let mut defs = XMLElement::new("defs");
+    fn generate_letter_def(&self, id: u16) -> XMLElement {
+        let text = if id == 0 {
+            "A".to_string()
+        } else {
+            let mut parts = Vec::new();
+            let mut id = id;
+
+            while id > 0 {
+                parts.push(
+                    char::from_u32('A' as u32 + id as u32 % 26).unwrap()
+                );
+                id /= 26;
+            }
 
-        for thread in self.fabric.threads().iter() {
-            let text = if thread.id == 0 {
-                "A".to_string()
-            } else {
-                let mut parts = Vec::new();
-                let mut id = thread.id;
-
-                while id > 0 {
-                    parts.push(
-                        char::from_u32('A' as u32 + id as u32 % 26).unwrap()
-                    );
-                    id /= 26;
+            parts.iter().rev().collect::<String>()
+        };
+
+        let mut element = XMLElement::new("text");
+
+        self.set_text_appearance(&mut element);
+        self.set_text_position(&mut element, 0.0, 0.0);
+
+        element.add_text(text);
+        element.add_attribute("id", format!("thread-{}", id));
+
+        element
+    }
+
+    // Builds the `d` attribute for one of `N_SYMBOLS` visually
+    // distinct monochrome glyphs, centered in a box of
+    // `box_width` × `box_height`, so that threads can be told apart
+    // without relying on color at all.
+    fn symbol_path_data(&self, symbol_index: usize) -> String {
+        let cx = self.box_width / 2.0;
+        let cy = self.box_height / 2.0;
+        let r = self.box_width.min(self.box_height) * 0.35;
+
+        match symbol_index {
+            // Filled circle, drawn as two half-circle arcs.
+            0 => format!(
+                "M {} {} a {} {} 0 1 0 {} 0 a {} {} 0 1 0 -{} 0 z",
+                cx - r, cy,
+                r, r, r * 2.0,
+                r, r, r * 2.0,
+            ),
+            // Upward-pointing triangle.
+            1 => format!(
+                "M {} {} L {} {} L {} {} Z",
+                cx, cy - r,
+                cx - r, cy + r,
+                cx + r, cy + r,
+            ),
+            // Cross.
+            2 => {
+                let w = r * 0.35;
+
+                format!(
+                    "M {} {} l {} 0 l 0 {} l {} 0 l 0 {} l -{} 0 l 0 {} \
+                     l -{} 0 l 0 -{} l -{} 0 l 0 -{} l {} 0 Z",
+                    cx - w, cy - r,
+                    w * 2.0,
+                    r - w,
+                    r - w,
+                    w * 2.0,
+                    r - w,
+                    w * 2.0,
+                    r - w,
+                    w * 2.0,
+                    r - w,
+                    w * 2.0,
+                    r - w,
+                )
+            },
+            // Diamond.
+            3 => format!(
+                "M {} {} L {} {} L {} {} L {} {} Z",
+                cx, cy - r,
+                cx + r, cy,
+                cx, cy + r,
+                cx - r, cy,
+            ),
+            // Five-pointed star.
+            4 => {
+                let mut points = Vec::with_capacity(10);
+
+                for i in 0..10 {
+                    let angle = -std::f32::consts::FRAC_PI_2
+                        + i as f32 * std::f32::consts::PI / 5.0;
+                    let radius = if i % 2 == 0 { r } else { r * 0.4 };
+
+                    points.push((
+                        cx + radius * angle.cos(),
+                        cy + radius * angle.sin(),
+                    ));
                 }
 
-                parts.iter().rev().collect::<String>()
-            };
+                let mut d = format!("M {} {}", points[0].0, points[0].1);
+
+                for &(x, y) in &points[1..] {
+                    write!(d, " L {} {}", x, y).unwrap();
+                }
+
+                d.push_str(" Z");
+
+                d
+            },
+            // Diagonal cross, drawn as two filled bars so that it
+            // takes the same fill as the other glyphs.
+            _ => {
+                let w = r * 0.35;
+
+                format!(
+                    "M {} {} L {} {} L {} {} L {} {} Z \
+                     M {} {} L {} {} L {} {} L {} {} Z",
+                    cx - r + w, cy - r,
+                    cx + r, cy + r - w,
+                    cx + r - w, cy + r,
+                    cx - r, cy - r + w,
+                    cx + r - w, cy - r,
+                    cx + r, cy - r + w,
+                    cx - r + w, cy + r,
+                    cx - r, cy + r - w,
+                )
+            },
+        }
+    }
+
+    fn generate_symbol_def(&self, id: u16) -> XMLElement {
+        let mut element = XMLElement::new("path");
+
+        element.add_attribute(
+            "d",
+            self.symbol_path_data(id as usize % N_SYMBOLS),
+        );
+        element.add_attribute("id", format!("thread-{}", id));
 
-            let mut element = XMLElement::new("text");
+        element
+    }
 
-            self.set_text_appearance(&mut element);
-            self.set_text_position(&mut element, 0.0, 0.0);
+    fn generate_defs(&self) -> XMLElement {
+        let mut defs = XMLElement::new("defs");
 
-            element.add_text(text);
-            element.add_attribute("id", format!("thread-{}", thread.id));
+        for thread in self.fabric.threads().iter() {
+            let element = if self.dimensions.stitch_text == StitchText::Symbol
+            {
+                self.generate_symbol_def(thread.id)
+            } else {
+                self.generate_letter_def(thread.id)
+            };
 
             defs.add_child(element);
         }
@@ -320,7 +519,12 @@ impl<'a> SvgGenerator<'a> {
     }
 }
 
-pub fn convert(dimensions: &Dimensions, fabric: &Fabric) -> XMLElement {
+fn convert_window(
+    dimensions: &Dimensions,
+    fabric: &Fabric,
+    window: Page,
+    page_legend: Option<(usize, usize)>,
+) -> XMLElement {
     let generator = SvgGenerator {
         dimensions: dimensions,
         box_width: BOX_WIDTH,
@@ -328,14 +532,17 @@ pub fn convert(dimensions: &Dimensions, fabric: &Fabric) -> XMLElement {
             * dimensions.gauge_stitches as f32
             / dimensions.gauge_rows as f32,
         fabric,
+        window,
     };
 
     let mut svg = XMLElement::new("svg");
 
-    let svg_width = ((fabric.n_stitches() + 1) as f32 * BOX_WIDTH)
+    let footer_rows = if page_legend.is_some() { 1 } else { 0 };
+
+    let svg_width = ((window.width + 1) as f32 * BOX_WIDTH)
         + LINE_WIDTH / 2.0;
-    let svg_height = ((fabric.n_rows() as usize + 2 + fabric.threads().len())
-                      as f32
+    let svg_height = ((window.height + 2 + footer_rows
+                       + fabric.threads().len() as u16) as f32
                       * generator.box_height)
         + LINE_WIDTH;
 
@@ -363,11 +570,37 @@ pub fn convert(dimensions: &Dimensions, fabric: &Fabric) -> XMLElement {
 
     translation.add_child(generator.generate_thread_counts());
 
+    if let Some((page_index, n_pages)) = page_legend {
+        translation.add_child(
+            generator.generate_page_legend(page_index, n_pages)
+        );
+    }
+
     svg.add_child(translation);
 
     svg
 }
 
+pub fn convert(dimensions: &Dimensions, fabric: &Fabric) -> XMLElement {
+    let window = Page::whole(fabric.n_stitches(), fabric.n_rows());
+
+    convert_window(dimensions, fabric, window, None)
+}
+
+// Renders just the sub-rectangle of the fabric covered by `page`,
+// for use by the pagination subsystem: each page becomes its own
+// SVG with its own ruler numbers and a footer
+// identifying its position among `n_pages` total pages.
+pub fn convert_page(
+    dimensions: &Dimensions,
+    fabric: &Fabric,
+    page: Page,
+    page_index: usize,
+    n_pages: usize,
+) -> XMLElement {
+    convert_window(dimensions, fabric, page, Some((page_index, n_pages)))
+}
+
 fn mm_to_text(mut out: impl Write, mm: u32) -> fmt::Result {
     if mm < 10 {
         write!(out, "{}mm", mm)?;
@@ -0,0 +1,338 @@
+// Stichify – A utility to generate intarsia knitting patterns
+// Copyright (C) 2025  Neil Roberts
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::str::FromStr;
+use std::fmt;
+use super::stitch_image::{Color, Image};
+
+// A `.crop(x, y, w, h)` argument parsed from the command line, in the
+// same “x,y,w,h” comma-separated form as `Link`.
+#[derive(Clone, Copy, Debug)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Debug)]
+pub enum CropParseError {
+    MissingElement,
+    TooManyElements,
+    ParseIntError(std::num::ParseIntError),
+}
+
+impl From<std::num::ParseIntError> for CropParseError {
+    fn from(e: std::num::ParseIntError) -> CropParseError {
+        CropParseError::ParseIntError(e)
+    }
+}
+
+impl std::error::Error for CropParseError {
+}
+
+impl fmt::Display for CropParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CropParseError::ParseIntError(e) => write!(f, "{}", e),
+            CropParseError::MissingElement
+                | CropParseError::TooManyElements =>
+            {
+                write!(f, "Crop argument must be of the form “x,y,w,h”")
+            },
+        }
+    }
+}
+
+impl FromStr for Crop {
+    type Err = CropParseError;
+
+    fn from_str(s: &str) -> Result<Crop, CropParseError> {
+        let mut crop = Crop { x: 0, y: 0, w: 0, h: 0 };
+        let mut value_count = 0usize;
+
+        for part in s.split(',') {
+            let part = part.parse::<u32>()?;
+
+            match value_count {
+                0 => crop.x = part,
+                1 => crop.y = part,
+                2 => crop.w = part,
+                3 => crop.h = part,
+                _ => return Err(CropParseError::TooManyElements),
+            }
+
+            value_count += 1;
+        }
+
+        if value_count < 4 {
+            Err(CropParseError::MissingElement)
+        } else {
+            Ok(crop)
+        }
+    }
+}
+
+// A fixed 90° rotation to apply to the input image before sampling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+// A strided, zero-copy view over another `Image`: `get_pixel(x, y)` is
+// remapped to `(ox + a*x + b*y, oy + c*x + d*y)` in the wrapped
+// image, where `(a, b, c, d)` is one of the eight signed permutation
+// matrices of a crop/rotate/flip composition. Building a view never
+// touches pixel data, only these coefficients.
+pub struct ImageView<'a, I: Image> {
+    image: &'a I,
+    width: u32,
+    height: u32,
+    ox: i64,
+    oy: i64,
+    a: i64,
+    b: i64,
+    c: i64,
+    d: i64,
+}
+
+impl<'a, I: Image> ImageView<'a, I> {
+    pub fn new(image: &'a I) -> ImageView<'a, I> {
+        ImageView {
+            width: image.width(),
+            height: image.height(),
+            image,
+            ox: 0,
+            oy: 0,
+            a: 1,
+            b: 0,
+            c: 0,
+            d: 1,
+        }
+    }
+
+    // Restricts the view to the `w`×`h` rectangle starting at `(x,
+    // y)`, in the current view's own coordinates.
+    pub fn crop(self, x: u32, y: u32, w: u32, h: u32) -> ImageView<'a, I> {
+        ImageView {
+            ox: self.ox + self.a * x as i64 + self.b * y as i64,
+            oy: self.oy + self.c * x as i64 + self.d * y as i64,
+            width: w,
+            height: h,
+            ..self
+        }
+    }
+
+    // Rotates the view 90° clockwise.
+    pub fn rotate90(self) -> ImageView<'a, I> {
+        let h = self.height as i64;
+
+        ImageView {
+            ox: self.ox + self.b * (h - 1),
+            oy: self.oy + self.d * (h - 1),
+            a: -self.b,
+            b: self.a,
+            c: -self.d,
+            d: self.c,
+            width: self.height,
+            height: self.width,
+            ..self
+        }
+    }
+
+    pub fn rotate180(self) -> ImageView<'a, I> {
+        self.rotate90().rotate90()
+    }
+
+    pub fn rotate270(self) -> ImageView<'a, I> {
+        self.rotate90().rotate90().rotate90()
+    }
+
+    // Mirrors the view left-to-right.
+    pub fn flip_h(self) -> ImageView<'a, I> {
+        let w = self.width as i64;
+
+        ImageView {
+            ox: self.ox + self.a * (w - 1),
+            oy: self.oy + self.c * (w - 1),
+            a: -self.a,
+            c: -self.c,
+            ..self
+        }
+    }
+
+    // Mirrors the view top-to-bottom.
+    pub fn flip_v(self) -> ImageView<'a, I> {
+        let h = self.height as i64;
+
+        ImageView {
+            ox: self.ox + self.b * (h - 1),
+            oy: self.oy + self.d * (h - 1),
+            b: -self.b,
+            d: -self.d,
+            ..self
+        }
+    }
+}
+
+impl<'a, I: Image> Image for ImageView<'a, I> {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Option<Color> {
+        let source_x = self.ox + self.a * x as i64 + self.b * y as i64;
+        let source_y = self.oy + self.c * x as i64 + self.d * y as i64;
+
+        self.image.get_pixel(source_x as u32, source_y as u32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A 4×3 image whose pixel value encodes its own coordinates, so
+    // transforms can be checked by reading off which source pixel
+    // ended up where.
+    struct CoordImage;
+
+    impl Image for CoordImage {
+        fn width(&self) -> u32 {
+            4
+        }
+
+        fn height(&self) -> u32 {
+            3
+        }
+
+        fn get_pixel(&self, x: u32, y: u32) -> Option<Color> {
+            Some([x as u8, y as u8, 0])
+        }
+    }
+
+    #[test]
+    fn identity_view_passes_through() {
+        let image = CoordImage;
+        let view = ImageView::new(&image);
+
+        assert_eq!(view.width(), 4);
+        assert_eq!(view.height(), 3);
+        assert_eq!(view.get_pixel(2, 1), Some([2, 1, 0]));
+    }
+
+    #[test]
+    fn crop_shifts_the_origin() {
+        let image = CoordImage;
+        let view = ImageView::new(&image).crop(1, 1, 2, 2);
+
+        assert_eq!(view.width(), 2);
+        assert_eq!(view.height(), 2);
+        assert_eq!(view.get_pixel(0, 0), Some([1, 1, 0]));
+        assert_eq!(view.get_pixel(1, 1), Some([2, 2, 0]));
+    }
+
+    #[test]
+    fn rotate90_swaps_dimensions_and_remaps_corners() {
+        let image = CoordImage;
+        let view = ImageView::new(&image).rotate90();
+
+        assert_eq!(view.width(), 3);
+        assert_eq!(view.height(), 4);
+
+        // The old bottom-left corner becomes the new top-left corner.
+        assert_eq!(view.get_pixel(0, 0), Some([0, 2, 0]));
+        // The old top-left corner becomes the new top-right corner.
+        assert_eq!(view.get_pixel(2, 0), Some([0, 0, 0]));
+        // The old top-right corner becomes the new bottom-right corner.
+        assert_eq!(view.get_pixel(2, 3), Some([3, 0, 0]));
+    }
+
+    #[test]
+    fn rotate180_reverses_both_axes() {
+        let image = CoordImage;
+        let view = ImageView::new(&image).rotate180();
+
+        assert_eq!(view.width(), 4);
+        assert_eq!(view.height(), 3);
+        assert_eq!(view.get_pixel(0, 0), Some([3, 2, 0]));
+        assert_eq!(view.get_pixel(3, 2), Some([0, 0, 0]));
+    }
+
+    #[test]
+    fn rotate270_is_the_inverse_of_rotate90() {
+        let image = CoordImage;
+        let view = ImageView::new(&image).rotate90().rotate270();
+
+        for y in 0..view.height() {
+            for x in 0..view.width() {
+                assert_eq!(view.get_pixel(x, y), image.get_pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn flip_h_mirrors_left_to_right() {
+        let image = CoordImage;
+        let view = ImageView::new(&image).flip_h();
+
+        assert_eq!(view.get_pixel(0, 0), Some([3, 0, 0]));
+        assert_eq!(view.get_pixel(3, 0), Some([0, 0, 0]));
+    }
+
+    #[test]
+    fn flip_v_mirrors_top_to_bottom() {
+        let image = CoordImage;
+        let view = ImageView::new(&image).flip_v();
+
+        assert_eq!(view.get_pixel(0, 0), Some([0, 2, 0]));
+        assert_eq!(view.get_pixel(0, 2), Some([0, 0, 0]));
+    }
+
+    #[test]
+    fn parses_crop_argument() {
+        let crop = "1,2,3,4".parse::<Crop>().unwrap();
+
+        assert_eq!(crop.x, 1);
+        assert_eq!(crop.y, 2);
+        assert_eq!(crop.w, 3);
+        assert_eq!(crop.h, 4);
+    }
+
+    #[test]
+    fn rejects_malformed_crop_argument() {
+        assert!("1,2,3".parse::<Crop>().is_err());
+        assert!("1,2,3,4,5".parse::<Crop>().is_err());
+    }
+
+    #[test]
+    fn crop_then_rotate_composes() {
+        let image = CoordImage;
+        let view = ImageView::new(&image).crop(1, 0, 3, 3).rotate90();
+
+        assert_eq!(view.width(), 3);
+        assert_eq!(view.height(), 3);
+        // The cropped region's bottom-left (source (1, 2)) becomes
+        // the rotated view's top-left.
+        assert_eq!(view.get_pixel(0, 0), Some([1, 2, 0]));
+    }
+}
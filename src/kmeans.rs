@@ -0,0 +1,278 @@
+// Stichify – A utility to generate intarsia knitting patterns
+// Copyright (C) 2025  Neil Roberts
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use super::stitch_image::{Color, Image};
+
+const MAX_ITERATIONS: usize = 50;
+
+fn squared_distance(a: Color, b: Color) -> u32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(&a, &b)| {
+            let diff = a as i32 - b as i32;
+            (diff * diff) as u32
+        })
+        .sum()
+}
+
+fn collect_points<I: Image>(image: &I) -> Vec<Color> {
+    let mut points = Vec::new();
+
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            if let Some(color) = image.get_pixel(x, y) {
+                points.push(color);
+            }
+        }
+    }
+
+    points
+}
+
+// Picks `k` initial centroids from `points` using k-means++ seeding:
+// the first centroid is uniform-random, and each subsequent one is
+// sampled with probability proportional to its squared distance to
+// the nearest centroid already chosen, so the seeds start out spread
+// across the color space rather than clumped together.
+fn seed_centroids(
+    points: &[Color],
+    k: usize,
+    rng: &mut ChaCha8Rng,
+) -> Vec<Color> {
+    let mut centroids = Vec::with_capacity(k);
+
+    centroids.push(points[rng.gen_range(0..points.len())]);
+
+    let mut distances = points.iter()
+        .map(|&point| squared_distance(point, centroids[0]))
+        .collect::<Vec<_>>();
+
+    while centroids.len() < k {
+        let total: u64 = distances.iter().map(|&d| d as u64).sum();
+
+        let chosen = if total == 0 {
+            rng.gen_range(0..points.len())
+        } else {
+            let mut target = rng.gen_range(0..total);
+
+            distances.iter()
+                .position(|&distance| {
+                    if target < distance as u64 {
+                        true
+                    } else {
+                        target -= distance as u64;
+                        false
+                    }
+                })
+                .unwrap_or(points.len() - 1)
+        };
+
+        let centroid = points[chosen];
+        centroids.push(centroid);
+
+        for (point, distance) in points.iter().zip(distances.iter_mut()) {
+            *distance = (*distance).min(squared_distance(*point, centroid));
+        }
+    }
+
+    centroids
+}
+
+// Runs Lloyd’s algorithm (assign to nearest centroid, recompute each
+// centroid as the mean of its members) from the k-means++ seeds
+// until assignments stop changing or `MAX_ITERATIONS` is reached. A
+// cluster that loses all of its members is reseeded from whichever
+// point is currently farthest from its own centroid, rather than
+// being left to sit empty for the rest of the run.
+fn lloyd(points: &[Color], mut centroids: Vec<Color>) -> Vec<Color> {
+    let k = centroids.len();
+    let mut assignments = vec![0usize; points.len()];
+
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+
+        for (point, assignment) in points.iter().zip(assignments.iter_mut()) {
+            let nearest = (0..k)
+                .min_by_key(|&i| squared_distance(*point, centroids[i]))
+                .unwrap();
+
+            if nearest != *assignment {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![[0u64; 3]; k];
+        let mut counts = vec![0u64; k];
+
+        for (point, &assignment) in points.iter().zip(assignments.iter()) {
+            counts[assignment] += 1;
+
+            for (sum, &component) in
+                sums[assignment].iter_mut().zip(point.iter())
+            {
+                *sum += component as u64;
+            }
+        }
+
+        for cluster in 0..k {
+            if counts[cluster] == 0 {
+                let (&farthest, _) = points.iter()
+                    .zip(assignments.iter())
+                    .max_by_key(|&(&point, &assignment)| {
+                        squared_distance(point, centroids[assignment])
+                    })
+                    .unwrap();
+
+                centroids[cluster] = farthest;
+            } else {
+                centroids[cluster] = [
+                    (sums[cluster][0] / counts[cluster]) as u8,
+                    (sums[cluster][1] / counts[cluster]) as u8,
+                    (sums[cluster][2] / counts[cluster]) as u8,
+                ];
+            }
+        }
+    }
+
+    centroids
+}
+
+pub struct Palette {
+    colors: Vec<Color>,
+    mapping: HashMap<Color, Color>,
+}
+
+impl Palette {
+    pub fn build<I: Image>(image: &I, k: usize, seed: u64) -> Palette {
+        Palette::from_points(collect_points(image), k, seed)
+    }
+
+    fn from_points(points: Vec<Color>, k: usize, seed: u64) -> Palette {
+        if points.is_empty() || k == 0 {
+            return Palette { colors: Vec::new(), mapping: HashMap::new() };
+        }
+
+        let k = k.min(points.len());
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        let centroids = seed_centroids(&points, k, &mut rng);
+        let colors = lloyd(&points, centroids);
+
+        let mut mapping = HashMap::new();
+
+        for &point in &points {
+            let nearest = *colors.iter()
+                .min_by_key(|&&color| squared_distance(point, color))
+                .unwrap();
+
+            mapping.insert(point, nearest);
+        }
+
+        Palette { colors, mapping }
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    pub fn nearest(&self, color: Color) -> Color {
+        if let Some(&mapped) = self.mapping.get(&color) {
+            return mapped;
+        }
+
+        *self.colors.iter()
+            .min_by_key(|&&candidate| squared_distance(candidate, color))
+            .unwrap()
+    }
+}
+
+pub struct KMeansImage<'a, I> {
+    image: &'a I,
+    palette: Palette,
+}
+
+impl<'a, I: Image> KMeansImage<'a, I> {
+    pub fn new(image: &'a I, k: usize, seed: u64) -> KMeansImage<'a, I> {
+        KMeansImage {
+            image,
+            palette: Palette::build(image, k, seed),
+        }
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+}
+
+impl<'a, I: Image> Image for KMeansImage<'a, I> {
+    fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Option<Color> {
+        self.image.get_pixel(x, y).map(|color| self.palette.nearest(color))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kmeans_reduces_to_k_colors() {
+        let points = vec![
+            [0, 0, 0], [1, 0, 0], [0, 1, 0],
+            [250, 250, 250], [255, 250, 250], [250, 255, 250],
+        ];
+
+        let palette = Palette::from_points(points, 2, 42);
+
+        assert_eq!(palette.colors().len(), 2);
+        assert_eq!(palette.nearest([0, 0, 0]), palette.nearest([1, 0, 0]));
+        assert_ne!(
+            palette.nearest([0, 0, 0]),
+            palette.nearest([250, 250, 250]),
+        );
+    }
+
+    #[test]
+    fn empty_points_give_empty_palette() {
+        let palette = Palette::from_points(Vec::new(), 4, 0);
+
+        assert!(palette.colors().is_empty());
+    }
+
+    #[test]
+    fn k_is_clamped_to_the_number_of_points() {
+        let points = vec![[10, 20, 30], [40, 50, 60]];
+
+        let palette = Palette::from_points(points, 5, 7);
+
+        assert_eq!(palette.colors().len(), 2);
+    }
+}
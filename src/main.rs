@@ -16,72 +16,26 @@
 
 mod dimensions;
 mod config;
+mod dither;
 mod fabric;
 mod fabric_svg;
+mod gauge;
+mod image_view;
+mod kmeans;
 mod mitre;
+mod output;
+mod page;
+mod quantize;
 mod stitch_image;
 mod sampler;
+mod yarn;
 
 use std::process::ExitCode;
 use std::fs::File;
 use image::DynamicImage;
 use image::buffer::ConvertBuffer;
 use stitch_image::{Color, Image};
-use simple_xml_builder::XMLElement;
-
-struct DocumentWrapper {
-}
-
-struct ElementWrapper {
-    inner: XMLElement,
-}
-
-impl fabric_svg::Document for DocumentWrapper {
-    type Element = ElementWrapper;
-
-    fn create_element(&self, name: &str) -> ElementWrapper {
-        ElementWrapper {
-            inner: XMLElement::new(name),
-        }
-    }
-}
-
-impl fabric_svg::Element for ElementWrapper {
-    fn set_root_namespace(&mut self, namespace: &str) {
-        self.inner.add_attribute("xmlns", namespace);
-    }
-
-    fn add_namespace(&mut self, keyword: &str, namespace: &str) {
-        self.inner.add_attribute(
-            format!("xmlns:{}", keyword.to_string()),
-            namespace.to_string(),
-        );
-    }
-
-    fn add_child(&mut self, child: ElementWrapper) {
-        self.inner.add_child(child.inner);
-    }
-
-    fn add_text(&mut self, value: impl ToString) {
-        self.inner.add_text(value);
-    }
-
-    fn add_attribute(&mut self, name: &str, value: impl ToString) {
-        self.inner.add_attribute(name, value);
-    }
-
-    fn add_attribute_ns(
-        &mut self,
-        keyword: &str,
-        name: &str,
-        value: impl ToString
-    ) {
-        self.inner.add_attribute(
-            format!("{}:{}", keyword, name),
-            value,
-        );
-    }
-}
+use output::Format;
 
 struct ImageBufWrapper(image::RgbaImage);
 
@@ -105,27 +59,25 @@ impl Image for ImageBufWrapper {
     }
 }
 
-fn build_svg<I: Image>(
+fn build_fabric<I: Image>(
     image: &I,
     config: &config::Config,
-) -> Result<XMLElement, fabric::Error> {
-    if config.mitre {
-        let (fabric, dimensions) = mitre::make_mitre_fabric(
+    palette: Option<&[Color]>,
+) -> Result<(fabric::Fabric, dimensions::Dimensions), fabric::Error> {
+    if let Some(grid) = config.grid {
+        mitre::make_mitre_grid(
             image,
             &config.dimensions,
-        )?;
-
-        Ok(fabric_svg::convert(
-            &DocumentWrapper { },
-            &dimensions,
-            &fabric,
-        ).inner)
+            palette,
+            grid,
+            config.grid_checkerboard,
+        )
+    } else if config.mitre {
+        mitre::make_mitre_fabric(image, &config.dimensions, palette)
     } else {
-        Ok(fabric_svg::convert(
-            &DocumentWrapper { },
-            &config.dimensions,
-            &fabric::Fabric::new(image, &config.dimensions)?,
-        ).inner)
+        let fabric = fabric::Fabric::new(image, &config.dimensions)?;
+
+        Ok((fabric, config.dimensions.clone()))
     }
 }
 
@@ -165,26 +117,68 @@ fn main() -> ExitCode {
         },
     };
 
-    let svg = match build_svg(
-        &ImageBufWrapper(image),
-        &config,
-    ) {
-        Ok(svg) => svg,
-        Err(e) => {
-            eprintln!("{}", e);
-            return ExitCode::FAILURE;
+    let image = ImageBufWrapper(image);
+
+    let view = image_view::ImageView::new(&image);
+
+    let view = match config.crop {
+        Some(crop) => view.crop(crop.x, crop.y, crop.w, crop.h),
+        None => view,
+    };
+
+    let view = match config.rotate {
+        Some(image_view::Rotation::Rotate90) => view.rotate90(),
+        Some(image_view::Rotation::Rotate180) => view.rotate180(),
+        Some(image_view::Rotation::Rotate270) => view.rotate270(),
+        None => view,
+    };
+
+    let view = if config.flip_h { view.flip_h() } else { view };
+    let view = if config.flip_v { view.flip_v() } else { view };
+
+    let image = view;
+
+    let fabric_result = match config.k {
+        Some(k) => {
+            let image = kmeans::KMeansImage::new(&image, k as usize, config.seed);
+            let palette = image.palette().colors().to_vec();
+
+            build_fabric(&image, &config, Some(&palette))
+        },
+        None => match config.colors {
+            Some(n_colors) => {
+                let image =
+                    quantize::QuantizedImage::new(&image, n_colors as usize);
+                let palette = image.palette().colors().to_vec();
+
+                build_fabric(&image, &config, Some(&palette))
+            },
+            None => build_fabric(&image, &config, None),
         },
     };
 
-    let output = match File::create(&config.files.output) {
-        Ok(file) => file,
+    let (fabric, dimensions) = match fabric_result {
+        Ok(result) => result,
         Err(e) => {
-            eprintln!("{}: {}", config.files.output, e);
+            eprintln!("{}", e);
             return ExitCode::FAILURE;
         },
     };
 
-    match svg.write(output) {
+    warn_about_long_floats(&fabric);
+
+    let result = match config.page {
+        Some(page_config) => write_paginated(
+            &fabric,
+            &dimensions,
+            config.format,
+            page_config,
+            &config.files.output,
+        ),
+        None => write_single(&fabric, &dimensions, config.format, &config.files.output),
+    };
+
+    match result {
         Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
             eprintln!("{}: {}", config.files.output, e);
@@ -192,3 +186,91 @@ fn main() -> ExitCode {
         },
     }
 }
+
+// Warns about every strand longer than `--max-float`, since a float
+// that is too long can snag or pull the fabric out of shape and is
+// otherwise invisible unless the user studies the chart themselves.
+fn warn_about_long_floats(fabric: &fabric::Fabric) {
+    for strand in fabric.strands() {
+        if strand.exceeds_max {
+            eprintln!(
+                "Warning: float of {} stitches at row {}, stitch {} \
+                 exceeds the maximum float length",
+                strand.length,
+                strand.y,
+                strand.start_x,
+            );
+        }
+    }
+}
+
+fn write_single(
+    fabric: &fabric::Fabric,
+    dimensions: &dimensions::Dimensions,
+    format: Format,
+    output: &str,
+) -> Result<(), String> {
+    let file = File::create(output).map_err(|e| e.to_string())?;
+
+    match format {
+        Format::Svg => {
+            fabric_svg::convert(dimensions, fabric).write(file)
+                .map_err(|e| e.to_string())
+        },
+        Format::Png => {
+            output::write_png(fabric, dimensions, file)
+                .map_err(|e| e.to_string())
+        },
+        Format::Pdf => {
+            output::write_pdf(fabric, dimensions, file)
+                .map_err(|e| e.to_string())
+        },
+    }
+}
+
+// Writes the output as a tiled set of pages. A PDF export stays a
+// single multi-page file; SVG and PNG have no concept of pages, so
+// each page is instead written to its own numbered file.
+fn write_paginated(
+    fabric: &fabric::Fabric,
+    dimensions: &dimensions::Dimensions,
+    format: Format,
+    page_config: page::PageConfig,
+    output: &str,
+) -> Result<(), String> {
+    let pagination = page::paginate(
+        fabric.n_stitches(),
+        fabric.n_rows(),
+        page_config.width,
+        page_config.height,
+        page_config.overlap,
+    );
+    let n_pages = pagination.pages.len();
+
+    if format == Format::Pdf {
+        let file = File::create(output).map_err(|e| e.to_string())?;
+
+        return output::write_pdf_pages(fabric, dimensions, &pagination.pages, file)
+            .map_err(|e| e.to_string());
+    }
+
+    for (page_index, &window) in pagination.pages.iter().enumerate() {
+        let path = output::page_path(output, &window);
+        let file = File::create(&path).map_err(|e| format!("{}: {}", path, e))?;
+
+        match format {
+            Format::Svg => {
+                fabric_svg::convert_page(dimensions, fabric, window, page_index, n_pages)
+                    .write(file)
+                    .map_err(|e| format!("{}: {}", path, e))?;
+            },
+            Format::Png => {
+                output::write_png_page(fabric, dimensions, window, file)
+                    .map_err(|e| format!("{}: {}", path, e))?;
+            },
+            Format::Pdf => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
@@ -14,9 +14,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fmt;
+use std::str::FromStr;
 use super::fabric::{self, Fabric};
 use super::stitch_image::{Image, Color};
 use super::config::{Dimensions, Link};
+use super::image_view::ImageView;
 use super::sampler::Sampler;
 
 struct MitreImage {
@@ -41,17 +44,46 @@ impl Image for MitreImage {
 impl MitreImage {
     pub fn new<I: Image>(
         image: &I,
-        n_stitches: u16,
+        dimensions: &Dimensions,
+        palette: Option<&[Color]>,
     ) -> MitreImage {
+        let n_stitches = dimensions.stitches;
         let image_size = image.width().min(image.height());
         let sample_size = image_size as f32 / n_stitches as f32;
         let mut pixels =
             Vec::with_capacity((n_stitches * n_stitches * 2) as usize);
-        let sampler = Sampler::new(image, sample_size, sample_size);
+
+        let sampler = match palette {
+            Some(palette) if dimensions.dither => Sampler::new_dithered(
+                image,
+                sample_size,
+                sample_size,
+                palette.to_vec(),
+            ),
+            _ if dimensions.stochastic => Sampler::new_stochastic(
+                image,
+                sample_size,
+                sample_size,
+                dimensions.seed,
+            ),
+            _ => Sampler::new(image, sample_size, sample_size),
+        };
+
+        sampler.set_alpha_threshold(dimensions.alpha_threshold);
 
         for y in 0..n_stitches {
             let row_width = y + 1;
 
+            sampler.set_row_reversed(dimensions.serpentine && y % 2 == 1);
+
+            // The two halves of a mitred row are sampled through
+            // unrelated coordinate spaces (the right half swaps x and
+            // y to reuse the same triangle-sampling code rotated), so
+            // they are kept on separate error channels. Otherwise a
+            // dithered palette would diffuse quantization error from
+            // one half into an unrelated stitch of the other.
+            sampler.set_channel(0);
+
             for x in 0..row_width - 1 {
                 pixels.push(sampler.sample(x, y, 1));
             }
@@ -65,6 +97,8 @@ impl MitreImage {
                 None
             );
 
+            sampler.set_channel(1);
+
             pixels.push(sampler.sample_upper_right_triangle(row_width - 1, y));
 
             for x in 1..row_width {
@@ -79,22 +113,15 @@ impl MitreImage {
     }
 }
 
-pub fn make_mitre_fabric<I: Image>(
-    image: &I,
-    dimensions: &Dimensions,
-) -> Result<(Fabric, Dimensions), fabric::Error> {
-    let image = MitreImage::new(image, dimensions.stitches);
-
-    // Use stitches that are twice as wide as they are tall but force
-    // garter stitch
-    let mut dimensions = dimensions.clone();
-    dimensions.gauge_rows = dimensions.gauge_stitches * 2;
-    dimensions.duplicate_rows = 2;
-    dimensions.stitches = image.width() as u16;
+// Computes the links needed to join the two triangular halves across
+// the middle gap of a single mitred square, expressed in stitch
+// coordinates local to `image` (i.e. as if `image` were the whole
+// fabric: `dimensions.stitches == image.width()`). Callers that embed
+// a mitred square inside something larger (see `make_mitre_grid`)
+// offset these before adding them to the real `Dimensions`.
+fn mitre_seam_links<I: Image>(image: &I) -> Vec<Link> {
+    let mut links = Vec::new();
 
-    dimensions.allow_link_gaps = true;
-
-    // Automatically add links across the middle gaps
     if image.height() > 1 {
         let center = image.width() as u16 / 2;
 
@@ -113,20 +140,289 @@ pub fn make_mitre_fabric<I: Image>(
 
             let bottom_row = y * 2 + 3;
 
-            dimensions.links.push(Link {
+            links.push(Link {
                 source: (right_x + 1, bottom_row),
                 dest: (left_x + 1, bottom_row),
             });
-            dimensions.links.push(Link {
+            links.push(Link {
                 source: (left_x + 1, bottom_row + 1),
                 dest: (right_x + 1, bottom_row + 1),
             });
         }
     }
 
+    links
+}
+
+pub fn make_mitre_fabric<I: Image>(
+    image: &I,
+    dimensions: &Dimensions,
+    palette: Option<&[Color]>,
+) -> Result<(Fabric, Dimensions), fabric::Error> {
+    let image = MitreImage::new(image, dimensions, palette);
+
+    // Use stitches that are twice as wide as they are tall but force
+    // garter stitch
+    let mut dimensions = dimensions.clone();
+    dimensions.gauge_rows = dimensions.gauge_stitches * 2.0;
+    dimensions.duplicate_rows = 2;
+    dimensions.stitches = image.width() as u16;
+
+    dimensions.allow_link_gaps = true;
+
+    // Automatically add links across the middle gap
+    dimensions.links.extend(mitre_seam_links(&image));
+
     fabric::Fabric::new(&image, &dimensions).map(|fabric| (fabric, dimensions))
 }
 
+// A grid of mitred squares, each built from its own region of a
+// source image, combined into a single `Image` so the whole grid can
+// be sampled into one `Fabric` by `make_mitre_grid`.
+struct MitreGridImage {
+    tiles: Vec<MitreImage>,
+    cols: u16,
+    rows: u16,
+    tile_width: u16,
+    tile_height: u16,
+}
+
+impl Image for MitreGridImage {
+    fn width(&self) -> u32 {
+        self.cols as u32 * self.tile_width as u32
+    }
+
+    fn height(&self) -> u32 {
+        self.rows as u32 * self.tile_height as u32
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Option<Color> {
+        let tile_col = x / self.tile_width as u32;
+        let tile_row = y / self.tile_height as u32;
+        let local_x = x % self.tile_width as u32;
+        let local_y = y % self.tile_height as u32;
+
+        self.tiles[(tile_row * self.cols as u32 + tile_col) as usize]
+            .get_pixel(local_x, local_y)
+    }
+}
+
+// Converts a stitch position, given as a pixel column and a (post
+// row-duplication) fabric row, both 0-indexed from the top-left of
+// `grid_image`, into the 1-indexed-from-the-bottom-right `Link`
+// coordinate space that `Dimensions::links` uses. This is the inverse
+// of `Fabric::look_up_link_position`.
+fn link_pos(n_stitches: u16, n_rows: u16, x: u32, y_fabric: u32) -> (u16, u16) {
+    (n_stitches - x as u16, n_rows - y_fabric as u16)
+}
+
+// Adds a `Link` joining every pair of matching stitches along the
+// shared edges between neighbouring tiles, so a knitter can pick up
+// each square directly from its neighbour instead of seaming
+// afterwards.
+fn add_inter_tile_links(grid_image: &MitreGridImage, dimensions: &mut Dimensions) {
+    let n_stitches = dimensions.stitches;
+    let n_rows = grid_image.height() as u16 * 2;
+    let tile_width = grid_image.tile_width as u32;
+    let tile_height = grid_image.tile_height as u32;
+
+    // Vertical seams between tiles that sit side by side.
+    for tile_row in 0..grid_image.rows as u32 {
+        for tile_col in 0..grid_image.cols as u32 - 1 {
+            let left_col = (tile_col + 1) * tile_width - 1;
+            let right_col = (tile_col + 1) * tile_width;
+
+            for local_y in 0..tile_height {
+                let y = tile_row * tile_height + local_y;
+
+                let left = grid_image.get_pixel(left_col, y);
+                let right = grid_image.get_pixel(right_col, y);
+
+                if left.is_none() || left != right {
+                    continue;
+                }
+
+                for y_fabric in [y * 2, y * 2 + 1] {
+                    dimensions.links.push(Link {
+                        source: link_pos(n_stitches, n_rows, left_col, y_fabric),
+                        dest: link_pos(n_stitches, n_rows, right_col, y_fabric),
+                    });
+                }
+            }
+        }
+    }
+
+    // Horizontal seams between tiles that are stacked on top of each
+    // other.
+    for tile_row in 0..grid_image.rows as u32 - 1 {
+        for tile_col in 0..grid_image.cols as u32 {
+            let top_row = (tile_row + 1) * tile_height - 1;
+            let bottom_row = (tile_row + 1) * tile_height;
+
+            for local_x in 0..tile_width {
+                let x = tile_col * tile_width + local_x;
+
+                let top = grid_image.get_pixel(x, top_row);
+                let bottom = grid_image.get_pixel(x, bottom_row);
+
+                if top.is_none() || top != bottom {
+                    continue;
+                }
+
+                // The lower edge of the upper tile is its last
+                // (duplicated) fabric row; the upper edge of the
+                // lower tile is its first.
+                dimensions.links.push(Link {
+                    source: link_pos(n_stitches, n_rows, x, top_row * 2 + 1),
+                    dest: link_pos(n_stitches, n_rows, x, bottom_row * 2),
+                });
+            }
+        }
+    }
+}
+
+// The number of mitred squares to tile, left to right and top to
+// bottom, parsed from the command line as e.g. “3x2”.
+#[derive(Clone, Copy, Debug)]
+pub struct GridSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+#[derive(Debug)]
+pub enum GridSizeParseError {
+    MissingElement,
+    TooManyElements,
+    ZeroSize,
+    ParseIntError(std::num::ParseIntError),
+}
+
+impl From<std::num::ParseIntError> for GridSizeParseError {
+    fn from(e: std::num::ParseIntError) -> GridSizeParseError {
+        GridSizeParseError::ParseIntError(e)
+    }
+}
+
+impl std::error::Error for GridSizeParseError {
+}
+
+impl fmt::Display for GridSizeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GridSizeParseError::ParseIntError(e) => write!(f, "{}", e),
+            GridSizeParseError::MissingElement
+                | GridSizeParseError::TooManyElements =>
+            {
+                write!(f, "Grid size must be of the form “colsxrows”")
+            },
+            GridSizeParseError::ZeroSize => {
+                write!(f, "Grid size must have at least one column and row")
+            },
+        }
+    }
+}
+
+impl FromStr for GridSize {
+    type Err = GridSizeParseError;
+
+    fn from_str(s: &str) -> Result<GridSize, GridSizeParseError> {
+        let mut parts = s.split('x');
+
+        let cols = parts.next().ok_or(GridSizeParseError::MissingElement)?
+            .parse::<u16>()?;
+        let rows = parts.next().ok_or(GridSizeParseError::MissingElement)?
+            .parse::<u16>()?;
+
+        if parts.next().is_some() {
+            return Err(GridSizeParseError::TooManyElements);
+        }
+
+        if cols == 0 || rows == 0 {
+            return Err(GridSizeParseError::ZeroSize);
+        }
+
+        Ok(GridSize { cols, rows })
+    }
+}
+
+// Builds a grid of mitred squares from `cols × rows` regions of
+// `image`, combined into a single `Fabric` with the seams between
+// tiles expressed as `Link`s. When `checkerboard` is set, every other
+// tile is rotated 90° before its mitred square is generated, so the
+// diagonal ridges alternate direction the way a traditional mitred
+// square blanket does.
+pub fn make_mitre_grid<I: Image>(
+    image: &I,
+    dimensions: &Dimensions,
+    palette: Option<&[Color]>,
+    grid_size: GridSize,
+    checkerboard: bool,
+) -> Result<(Fabric, Dimensions), fabric::Error> {
+    let GridSize { cols, rows } = grid_size;
+
+    let tile_px_width = image.width() / cols as u32;
+    let tile_px_height = image.height() / rows as u32;
+
+    let mut tiles = Vec::with_capacity(cols as usize * rows as usize);
+
+    for tile_row in 0..rows {
+        for tile_col in 0..cols {
+            let view = ImageView::new(image).crop(
+                tile_col as u32 * tile_px_width,
+                tile_row as u32 * tile_px_height,
+                tile_px_width,
+                tile_px_height,
+            );
+
+            let rotated = checkerboard && (tile_row + tile_col) % 2 == 1;
+
+            tiles.push(if rotated {
+                MitreImage::new(&view.rotate90(), dimensions, palette)
+            } else {
+                MitreImage::new(&view, dimensions, palette)
+            });
+        }
+    }
+
+    let tile_width = tiles[0].width() as u16;
+    let tile_height = tiles[0].height() as u16;
+
+    let grid_image = MitreGridImage { tiles, cols, rows, tile_width, tile_height };
+
+    // Use stitches that are twice as wide as they are tall but force
+    // garter stitch, exactly as a single mitred square does.
+    let mut dimensions = dimensions.clone();
+    dimensions.gauge_rows = dimensions.gauge_stitches * 2.0;
+    dimensions.duplicate_rows = 2;
+    dimensions.stitches = grid_image.width() as u16;
+    dimensions.allow_link_gaps = true;
+
+    // Add links across each tile's own middle gap, offset to its
+    // position in the grid. `Link` positions count from the
+    // bottom-right, so a tile's offset is how many stitches/rows lie
+    // between it and the bottom-right corner of the grid.
+    for tile_row in 0..rows {
+        for tile_col in 0..cols {
+            let tile = &grid_image.tiles[(tile_row * cols + tile_col) as usize];
+
+            let x_offset = (cols - tile_col - 1) * tile_width;
+            let y_offset = (rows - tile_row - 1) * tile_height * 2;
+
+            for link in mitre_seam_links(tile) {
+                dimensions.links.push(Link {
+                    source: (link.source.0 + x_offset, link.source.1 + y_offset),
+                    dest: (link.dest.0 + x_offset, link.dest.1 + y_offset),
+                });
+            }
+        }
+    }
+
+    // Add links joining neighbouring tiles along their shared edges.
+    add_inter_tile_links(&grid_image, &mut dimensions);
+
+    fabric::Fabric::new(&grid_image, &dimensions).map(|fabric| (fabric, dimensions))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -202,7 +498,7 @@ mod test {
     fn mitre_image() {
         let fake_image = FakeImage { };
 
-        let image = MitreImage::new(&fake_image, 24);
+        let image = MitreImage::new(&fake_image, &Dimensions { stitches: 24, ..Dimensions::default() }, None);
 
         assert_eq!(image.width(), 48);
         assert_eq!(image.height(), 24);
@@ -225,6 +521,41 @@ mod test {
         assert_eq!(image.get_pixel(47, 0), Some([0, 0, 0]));
     }
 
+    struct GradientImage;
+
+    impl Image for GradientImage {
+        fn width(&self) -> u32 {
+            24
+        }
+
+        fn height(&self) -> u32 {
+            24
+        }
+
+        fn get_pixel(&self, x: u32, y: u32) -> Option<Color> {
+            Some([(x * 10) as u8, (y * 10) as u8, 0])
+        }
+    }
+
+    #[test]
+    fn dithered_mitre_image_snaps_both_halves_to_the_palette() {
+        let image = GradientImage { };
+        let palette = [[0, 0, 0], [255, 255, 255]];
+        let mut dimensions = Dimensions::default();
+        dimensions.stitches = 24;
+        dimensions.dither = true;
+
+        let image = MitreImage::new(&image, &dimensions, Some(&palette));
+
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                if let Some(color) = image.get_pixel(x, y) {
+                    assert!(palette.contains(&color));
+                }
+            }
+        }
+    }
+
     #[test]
     fn unlinked_diagonal_image() {
         let fake_image = FakeDiagonalImage {
@@ -235,7 +566,7 @@ mod test {
         dimensions.stitches = 3;
 
         let (fabric, dimensions) =
-            make_mitre_fabric(&fake_image, &dimensions).unwrap();
+            make_mitre_fabric(&fake_image, &dimensions, None).unwrap();
 
         assert_eq!(fabric.n_rows(), 6);
         assert_eq!(fabric.n_stitches(), 6);
@@ -281,7 +612,7 @@ mod test {
         dimensions.stitches = 3;
 
         let (fabric, dimensions) =
-            make_mitre_fabric(&fake_image, &dimensions).unwrap();
+            make_mitre_fabric(&fake_image, &dimensions, None).unwrap();
 
         assert_eq!(fabric.n_rows(), 6);
         assert_eq!(fabric.n_stitches(), 6);
@@ -309,4 +640,104 @@ mod test {
             [255, 0, 0],
         );
     }
+
+    #[test]
+    fn mitre_grid_matches_a_single_square_for_a_1x1_grid() {
+        let fake_image = FakeDiagonalImage {
+            data: LINKED_DIAGONAL_IMAGE_DATA.clone(),
+        };
+
+        let mut dimensions = Dimensions::default();
+        dimensions.stitches = 3;
+
+        let (single_fabric, single_dimensions) =
+            make_mitre_fabric(&fake_image, &dimensions, None).unwrap();
+        let (grid_fabric, grid_dimensions) = make_mitre_grid(
+            &fake_image,
+            &dimensions,
+            None,
+            GridSize { cols: 1, rows: 1 },
+            false,
+        ).unwrap();
+
+        assert_eq!(grid_fabric.n_stitches(), single_fabric.n_stitches());
+        assert_eq!(grid_fabric.n_rows(), single_fabric.n_rows());
+        assert_eq!(grid_dimensions.links.len(), single_dimensions.links.len());
+
+        for (grid_link, single_link) in
+            grid_dimensions.links.iter().zip(single_dimensions.links.iter())
+        {
+            assert_eq!(grid_link.source, single_link.source);
+            assert_eq!(grid_link.dest, single_link.dest);
+        }
+
+        let n_cells =
+            single_fabric.n_stitches() as usize * single_fabric.n_rows() as usize;
+
+        for i in 0..n_cells {
+            assert_eq!(
+                grid_fabric.stitches()[i].as_ref().map(|s| s.color),
+                single_fabric.stitches()[i].as_ref().map(|s| s.color),
+            );
+        }
+    }
+
+    struct SolidImage;
+
+    impl Image for SolidImage {
+        fn width(&self) -> u32 {
+            24
+        }
+
+        fn height(&self) -> u32 {
+            12
+        }
+
+        fn get_pixel(&self, _x: u32, _y: u32) -> Option<Color> {
+            Some([255, 0, 0])
+        }
+    }
+
+    #[test]
+    fn mitre_grid_joins_neighbouring_tiles_with_seam_links() {
+        let image = SolidImage;
+
+        let mut tile_dimensions = Dimensions::default();
+        tile_dimensions.stitches = 3;
+
+        let tile_seam_link_count = mitre_seam_links(
+            &MitreImage::new(&image, &tile_dimensions, None)
+        ).len();
+
+        let (fabric, dimensions) = make_mitre_grid(
+            &image,
+            &tile_dimensions,
+            None,
+            GridSize { cols: 2, rows: 1 },
+            false,
+        ).unwrap();
+
+        assert_eq!(fabric.n_stitches(), 12);
+        assert_eq!(fabric.n_rows(), 6);
+
+        // A solid-colour image matches on both sides of every tile
+        // boundary, so at least one seam link should have been added
+        // on top of the two tiles' own middle-gap links.
+        assert!(dimensions.links.len() > 2 * tile_seam_link_count);
+    }
+
+    #[test]
+    fn parses_grid_size_argument() {
+        let grid_size = "3x2".parse::<GridSize>().unwrap();
+
+        assert_eq!(grid_size.cols, 3);
+        assert_eq!(grid_size.rows, 2);
+    }
+
+    #[test]
+    fn rejects_malformed_grid_size_argument() {
+        assert!("3".parse::<GridSize>().is_err());
+        assert!("3x2x1".parse::<GridSize>().is_err());
+        assert!("0x2".parse::<GridSize>().is_err());
+    }
 }
@@ -0,0 +1,400 @@
+// Stichify – A utility to generate intarsia knitting patterns
+// Copyright (C) 2025  Neil Roberts
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::fabric::Fabric;
+use super::dimensions::Dimensions;
+use super::page::Page;
+use image::{Rgba, RgbaImage};
+use std::io::{self, Seek, Write};
+
+// Pixel size of a single stitch box in the raster and PDF backends.
+// This matches the point size that `fabric_svg` uses for the SVG
+// backend so that all three outputs are the same physical size.
+const BOX_SIZE: u32 = 20;
+
+const GRID_COLOR: Rgba<u8> = Rgba([181, 181, 181, 255]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Svg,
+    Png,
+    Pdf,
+}
+
+impl Format {
+    // Guesses the output format from the extension of `path`,
+    // falling back to SVG if the extension is missing or
+    // unrecognised.
+    pub fn from_path(path: &str) -> Format {
+        let extension = match path.rsplit_once('.') {
+            Some((_, extension)) => extension.to_lowercase(),
+            None => return Format::Svg,
+        };
+
+        match extension.as_str() {
+            "png" => Format::Png,
+            "pdf" => Format::Pdf,
+            _ => Format::Svg,
+        }
+    }
+}
+
+fn box_height(dimensions: &Dimensions) -> u32 {
+    (BOX_SIZE as f32 * dimensions.gauge_stitches / dimensions.gauge_rows)
+        .round()
+        .max(1.0) as u32
+}
+
+fn fill_rect(
+    image: &mut RgbaImage,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    color: Rgba<u8>,
+) {
+    for py in y..(y + height).min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.put_pixel(px, py, color);
+        }
+    }
+}
+
+// Renders the sub-rectangle of the fabric covered by `window` as a
+// flat grid of colored boxes with a thin grid line between each
+// stitch. This intentionally mirrors the geometry of
+// `fabric_svg::convert` rather than parsing its SVG output, since
+// `simple_xml_builder`’s `XMLElement` is a write-only builder.
+//
+// KNOWN GAP: unlike the SVG backend, this (and `page_content` below,
+// used by the PDF backend) draws none of `fabric_svg`'s text
+// overlays — no `stitch_text` thread letters/run counts/symbols, no
+// ruler numbers along the edges, and no page legend — because doing
+// so would need a font rasterizer that isn’t otherwise part of the
+// dependency tree. `--format png`/`--format pdf` is therefore a
+// colors-and-grid-only chart, not a like-for-like replacement for
+// `--format svg`.
+pub fn render_png_page(
+    fabric: &Fabric,
+    dimensions: &Dimensions,
+    window: Page,
+) -> RgbaImage {
+    let box_height = box_height(dimensions);
+
+    let width = window.width as u32 * BOX_SIZE + 1;
+    let height = window.height as u32 * box_height + 1;
+
+    let mut image = RgbaImage::from_pixel(
+        width,
+        height,
+        Rgba([255, 255, 255, 255]),
+    );
+
+    for y in window.start_y..window.start_y + window.height {
+        for x in window.start_x..window.start_x + window.width {
+            let Some(stitch) = fabric.stitches()[
+                (x + y * fabric.n_stitches()) as usize
+            ].as_ref() else { continue };
+
+            fill_rect(
+                &mut image,
+                (x - window.start_x) as u32 * BOX_SIZE,
+                (y - window.start_y) as u32 * box_height,
+                BOX_SIZE,
+                box_height,
+                Rgba([
+                    stitch.color[0],
+                    stitch.color[1],
+                    stitch.color[2],
+                    255,
+                ]),
+            );
+        }
+    }
+
+    for x in 0..=window.width as u32 {
+        fill_rect(&mut image, x * BOX_SIZE, 0, 1, height, GRID_COLOR);
+    }
+
+    for y in 0..=window.height as u32 {
+        fill_rect(&mut image, 0, y * box_height, width, 1, GRID_COLOR);
+    }
+
+    image
+}
+
+pub fn render_png(fabric: &Fabric, dimensions: &Dimensions) -> RgbaImage {
+    render_png_page(
+        fabric,
+        dimensions,
+        Page::whole(fabric.n_stitches(), fabric.n_rows()),
+    )
+}
+
+pub fn write_png(
+    fabric: &Fabric,
+    dimensions: &Dimensions,
+    writer: impl Write + Seek,
+) -> image::ImageResult<()> {
+    write_png_page(
+        fabric,
+        dimensions,
+        Page::whole(fabric.n_stitches(), fabric.n_rows()),
+        writer,
+    )
+}
+
+pub fn write_png_page(
+    fabric: &Fabric,
+    dimensions: &Dimensions,
+    window: Page,
+    writer: impl Write + Seek,
+) -> image::ImageResult<()> {
+    let image = render_png_page(fabric, dimensions, window);
+
+    let mut writer = io::BufWriter::new(writer);
+
+    image.write_to(&mut writer, image::ImageFormat::Png)
+}
+
+struct PdfWriter {
+    buf: Vec<u8>,
+    object_offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn new() -> PdfWriter {
+        let mut writer = PdfWriter {
+            buf: Vec::new(),
+            object_offsets: Vec::new(),
+        };
+
+        writer.buf.extend_from_slice(b"%PDF-1.4\n");
+
+        writer
+    }
+
+    fn begin_object(&mut self) -> usize {
+        self.object_offsets.push(self.buf.len());
+
+        let n = self.object_offsets.len();
+
+        write!(&mut self.buf, "{} 0 obj\n", n).unwrap();
+
+        n
+    }
+
+    fn end_object(&mut self) {
+        self.buf.extend_from_slice(b"endobj\n");
+    }
+
+    fn add_stream(&mut self, content: &[u8]) -> usize {
+        let n = self.begin_object();
+
+        write!(&mut self.buf, "<< /Length {} >>\nstream\n", content.len())
+            .unwrap();
+        self.buf.extend_from_slice(content);
+        self.buf.extend_from_slice(b"\nendstream\n");
+
+        self.end_object();
+
+        n
+    }
+
+    fn finish(mut self, catalog: usize) -> Vec<u8> {
+        let xref_offset = self.buf.len();
+        let n_objects = self.object_offsets.len();
+
+        write!(&mut self.buf, "xref\n0 {}\n", n_objects + 1).unwrap();
+        self.buf.extend_from_slice(b"0000000000 65535 f \n");
+
+        for offset in self.object_offsets.iter() {
+            write!(&mut self.buf, "{:010} 00000 n \n", offset).unwrap();
+        }
+
+        write!(
+            &mut self.buf,
+            "trailer\n<< /Size {} /Root {} 0 R >>\n",
+            n_objects + 1,
+            catalog,
+        ).unwrap();
+
+        write!(&mut self.buf, "startxref\n{}\n%%EOF\n", xref_offset).unwrap();
+
+        self.buf
+    }
+}
+
+fn page_content(
+    fabric: &Fabric,
+    dimensions: &Dimensions,
+    window: Page,
+    page_height: f32,
+) -> Vec<u8> {
+    let box_height = box_height(dimensions) as f32;
+    let mut content = Vec::new();
+
+    for y in window.start_y..window.start_y + window.height {
+        for x in window.start_x..window.start_x + window.width {
+            let Some(stitch) = fabric.stitches()[
+                (x + y * fabric.n_stitches()) as usize
+            ].as_ref() else { continue };
+
+            let local_x = (x - window.start_x) as f32;
+            let local_y = (y - window.start_y) as f32;
+
+            // PDF user space has its origin at the bottom-left,
+            // whereas the fabric is stored top row first, so flip
+            // the y axis.
+            let pdf_y = page_height - (local_y + 1.0) * box_height;
+
+            write!(
+                &mut content,
+                "{} {} {} rg\n{} {} {} {} re f\n",
+                stitch.color[0] as f32 / 255.0,
+                stitch.color[1] as f32 / 255.0,
+                stitch.color[2] as f32 / 255.0,
+                local_x * BOX_SIZE as f32,
+                pdf_y,
+                BOX_SIZE as f32,
+                box_height,
+            ).unwrap();
+        }
+    }
+
+    content.extend_from_slice(b"0.71 0.71 0.71 rg\n");
+
+    for x in 0..=window.width {
+        write!(
+            &mut content,
+            "{} 0 0.5 {} re f\n",
+            x as f32 * BOX_SIZE as f32,
+            page_height,
+        ).unwrap();
+    }
+
+    for y in 0..=window.height {
+        write!(
+            &mut content,
+            "0 {} {} 0.5 re f\n",
+            page_height - y as f32 * box_height,
+            window.width as f32 * BOX_SIZE as f32,
+        ).unwrap();
+    }
+
+    content
+}
+
+// Emits one PDF page per entry in `pages`, each its own `Contents`
+// stream and `MediaBox`, in a single multi-page document. Pairing
+// this with the pagination subsystem turns a large chart into a
+// ready-to-print booklet.
+pub fn render_pdf_pages(
+    fabric: &Fabric,
+    dimensions: &Dimensions,
+    pages: &[Page],
+) -> Vec<u8> {
+    // The object contents are computed up front so that the
+    // `/Pages` object can list its `Kids` by object number before
+    // any of the page objects themselves are written out.
+    let page_contents = pages.iter().map(|&window| {
+        let width = window.width as f32 * BOX_SIZE as f32;
+        let height = window.height as f32 * box_height(dimensions) as f32;
+        let content = page_content(fabric, dimensions, window, height);
+
+        (width, height, content)
+    }).collect::<Vec<_>>();
+
+    let mut writer = PdfWriter::new();
+
+    let catalog = writer.begin_object();
+    writer.buf.extend_from_slice(b"<< /Type /Catalog /Pages 2 0 R >>\n");
+    writer.end_object();
+
+    let pages_object = writer.begin_object();
+
+    // Object 3 is the first page, and each page occupies two
+    // objects (the page dictionary and its content stream).
+    let first_page_object = pages_object + 1;
+    let kids = (0..page_contents.len())
+        .map(|i| format!("{} 0 R", first_page_object + i * 2))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    write!(
+        &mut writer.buf,
+        "<< /Type /Pages /Kids [{}] /Count {} >>\n",
+        kids,
+        page_contents.len(),
+    ).unwrap();
+    writer.end_object();
+
+    for (width, height, content) in page_contents {
+        let page_object = writer.begin_object();
+
+        write!(
+            &mut writer.buf,
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] \
+             /Contents {} 0 R /Resources << >> >>\n",
+            pages_object,
+            width,
+            height,
+            page_object + 1,
+        ).unwrap();
+        writer.end_object();
+
+        writer.add_stream(&content);
+    }
+
+    writer.finish(catalog)
+}
+
+// Derives the filename for one page of a multi-page SVG/PNG export
+// by inserting the page’s column/row into `path`, just before the
+// extension (or at the end if there is none).
+pub fn page_path(path: &str, page: &Page) -> String {
+    let suffix = format!("-{}-{}", page.page_x, page.page_y);
+
+    match path.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}{}.{}", stem, suffix, extension),
+        None => format!("{}{}", path, suffix),
+    }
+}
+
+pub fn render_pdf(fabric: &Fabric, dimensions: &Dimensions) -> Vec<u8> {
+    render_pdf_pages(
+        fabric,
+        dimensions,
+        &[Page::whole(fabric.n_stitches(), fabric.n_rows())],
+    )
+}
+
+pub fn write_pdf(
+    fabric: &Fabric,
+    dimensions: &Dimensions,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    writer.write_all(&render_pdf(fabric, dimensions))
+}
+
+pub fn write_pdf_pages(
+    fabric: &Fabric,
+    dimensions: &Dimensions,
+    pages: &[Page],
+    mut writer: impl Write,
+) -> io::Result<()> {
+    writer.write_all(&render_pdf_pages(fabric, dimensions, pages))
+}
@@ -0,0 +1,151 @@
+// Stichify – A utility to generate intarsia knitting patterns
+// Copyright (C) 2025  Neil Roberts
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// A sub-rectangle of the fabric grid, in stitch/row coordinates,
+// that a single printed page should cover. `page_x`/`page_y` are
+// this page’s position in the grid of pages, used for the legend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub start_x: u16,
+    pub start_y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub page_x: u16,
+    pub page_y: u16,
+}
+
+impl Page {
+    pub fn whole(n_stitches: u16, n_rows: u16) -> Page {
+        Page {
+            start_x: 0,
+            start_y: 0,
+            width: n_stitches,
+            height: n_rows,
+            page_x: 0,
+            page_y: 0,
+        }
+    }
+}
+
+// The page-size options as given on the command line. `width` and
+// `height` are in stitches/rows rather than a physical unit, since
+// the rest of the configuration already thinks in those terms.
+#[derive(Clone, Copy)]
+pub struct PageConfig {
+    pub width: u16,
+    pub height: u16,
+    pub overlap: u16,
+}
+
+pub struct Pagination {
+    pub pages: Vec<Page>,
+    pub n_page_columns: u16,
+    pub n_page_rows: u16,
+}
+
+fn ceil_div(a: u16, b: u16) -> u16 {
+    (a + b - 1) / b
+}
+
+fn n_pages_along_axis(n_cells: u16, page_size: u16, overlap: u16) -> u16 {
+    if n_cells <= page_size {
+        return 1;
+    }
+
+    let step = page_size.saturating_sub(overlap).max(1);
+
+    1 + ceil_div(n_cells - page_size, step)
+}
+
+fn page_starts(n_cells: u16, page_size: u16, overlap: u16) -> Vec<u16> {
+    let n_pages = n_pages_along_axis(n_cells, page_size, overlap);
+    let step = page_size.saturating_sub(overlap).max(1);
+    let last_start = n_cells.saturating_sub(page_size);
+
+    (0..n_pages)
+        .map(|page_index| (page_index * step).min(last_start))
+        .collect()
+}
+
+// Slices a `n_stitches` × `n_rows` grid into a tiled set of pages,
+// each at most `page_width` × `page_height` cells, with `overlap`
+// cells of shared context between adjacent pages so the printed
+// pages can be lined up and taped together.
+pub fn paginate(
+    n_stitches: u16,
+    n_rows: u16,
+    page_width: u16,
+    page_height: u16,
+    overlap: u16,
+) -> Pagination {
+    let x_starts = page_starts(n_stitches, page_width, overlap);
+    let y_starts = page_starts(n_rows, page_height, overlap);
+
+    let mut pages = Vec::with_capacity(x_starts.len() * y_starts.len());
+
+    for (page_y, &start_y) in y_starts.iter().enumerate() {
+        let height = page_height.min(n_rows - start_y);
+
+        for (page_x, &start_x) in x_starts.iter().enumerate() {
+            let width = page_width.min(n_stitches - start_x);
+
+            pages.push(Page {
+                start_x,
+                start_y,
+                width,
+                height,
+                page_x: page_x as u16,
+                page_y: page_y as u16,
+            });
+        }
+    }
+
+    Pagination {
+        pages,
+        n_page_columns: x_starts.len() as u16,
+        n_page_rows: y_starts.len() as u16,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fits_on_one_page() {
+        let pagination = paginate(10, 10, 20, 20, 2);
+
+        assert_eq!(pagination.n_page_columns, 1);
+        assert_eq!(pagination.n_page_rows, 1);
+        assert_eq!(pagination.pages.len(), 1);
+        assert_eq!(pagination.pages[0], Page::whole(10, 10));
+    }
+
+    #[test]
+    fn splits_with_overlap() {
+        let pagination = paginate(25, 10, 10, 10, 2);
+
+        assert_eq!(pagination.n_page_columns, 3);
+        assert_eq!(pagination.n_page_rows, 1);
+
+        assert_eq!(pagination.pages[0].start_x, 0);
+        assert_eq!(pagination.pages[0].width, 10);
+        assert_eq!(pagination.pages[1].start_x, 8);
+        assert_eq!(pagination.pages[1].width, 10);
+        assert_eq!(pagination.pages[2].start_x, 15);
+        assert_eq!(pagination.pages[2].width, 10);
+    }
+}
@@ -0,0 +1,240 @@
+// Stichify – A utility to generate intarsia knitting patterns
+// Copyright (C) 2025  Neil Roberts
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use super::stitch_image::{Color, Image};
+
+fn channel_range(points: &[Color], channel: usize) -> u16 {
+    let mut min = u8::MAX;
+    let mut max = u8::MIN;
+
+    for point in points {
+        min = min.min(point[channel]);
+        max = max.max(point[channel]);
+    }
+
+    (max - min) as u16
+}
+
+fn widest_channel(points: &[Color]) -> usize {
+    (0..3)
+        .max_by_key(|&channel| channel_range(points, channel))
+        .unwrap()
+}
+
+fn split_box(mut points: Vec<Color>) -> (Vec<Color>, Vec<Color>) {
+    let channel = widest_channel(&points);
+
+    points.sort_unstable_by_key(|point| point[channel]);
+
+    let upper = points.split_off(points.len() / 2);
+
+    (points, upper)
+}
+
+fn average_color(points: &[Color]) -> Color {
+    let mut sums = [0u32; 3];
+
+    for point in points {
+        for (sum, &component) in sums.iter_mut().zip(point.iter()) {
+            *sum += component as u32;
+        }
+    }
+
+    let n_points = points.len() as u32;
+
+    [
+        (sums[0] / n_points) as u8,
+        (sums[1] / n_points) as u8,
+        (sums[2] / n_points) as u8,
+    ]
+}
+
+fn squared_distance(a: Color, b: Color) -> u32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(&a, &b)| {
+            let diff = a as i32 - b as i32;
+            (diff * diff) as u32
+        })
+        .sum()
+}
+
+// Median-cut color quantization: repeatedly split the box whose
+// widest channel has the greatest spread, along that channel, at
+// its median point, until there are `n_colors` boxes or no box can
+// be split any further.
+fn median_cut_boxes(points: Vec<Color>, n_colors: usize) -> Vec<Vec<Color>> {
+    if points.is_empty() || n_colors == 0 {
+        return Vec::new();
+    }
+
+    let mut boxes = vec![points];
+
+    while boxes.len() < n_colors {
+        let widest = boxes.iter()
+            .enumerate()
+            .filter(|(_, points)| {
+                points.len() > 1 &&
+                    channel_range(points, widest_channel(points)) > 0
+            })
+            .max_by_key(|(_, points)| channel_range(
+                points,
+                widest_channel(points),
+            ));
+
+        let Some((index, _)) = widest else { break };
+
+        let (lower, upper) = split_box(boxes.swap_remove(index));
+
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    boxes
+}
+
+pub struct Palette {
+    colors: Vec<Color>,
+    mapping: HashMap<Color, Color>,
+}
+
+impl Palette {
+    pub fn build<I: Image>(image: &I, n_colors: usize) -> Palette {
+        let mut points = Vec::new();
+
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                if let Some(color) = image.get_pixel(x, y) {
+                    points.push(color);
+                }
+            }
+        }
+
+        Palette::from_points(points, n_colors)
+    }
+
+    pub fn from_points(points: Vec<Color>, n_colors: usize) -> Palette {
+        let boxes = median_cut_boxes(points, n_colors);
+
+        let mut colors = Vec::with_capacity(boxes.len());
+        let mut mapping = HashMap::new();
+
+        for box_points in boxes {
+            let average = average_color(&box_points);
+
+            colors.push(average);
+
+            for color in box_points {
+                mapping.insert(color, average);
+            }
+        }
+
+        Palette { colors, mapping }
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    pub fn nearest(&self, color: Color) -> Color {
+        if let Some(&mapped) = self.mapping.get(&color) {
+            return mapped;
+        }
+
+        *self.colors.iter()
+            .min_by_key(|&&candidate| squared_distance(candidate, color))
+            .unwrap()
+    }
+}
+
+pub struct QuantizedImage<'a, I> {
+    image: &'a I,
+    palette: Palette,
+}
+
+impl<'a, I: Image> QuantizedImage<'a, I> {
+    pub fn new(image: &'a I, n_colors: usize) -> QuantizedImage<'a, I> {
+        QuantizedImage {
+            image,
+            palette: Palette::build(image, n_colors),
+        }
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+}
+
+impl<'a, I: Image> Image for QuantizedImage<'a, I> {
+    fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Option<Color> {
+        self.image.get_pixel(x, y).map(|color| self.palette.nearest(color))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn median_cut_splits_on_widest_channel() {
+        let points = vec![
+            [0, 100, 100],
+            [10, 100, 100],
+            [250, 100, 100],
+            [255, 100, 100],
+        ];
+
+        let boxes = median_cut_boxes(points, 2);
+
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(boxes[0], vec![[0, 100, 100], [10, 100, 100]]);
+        assert_eq!(boxes[1], vec![[250, 100, 100], [255, 100, 100]]);
+    }
+
+    #[test]
+    fn median_cut_stops_when_no_box_can_split() {
+        let points = vec![[1, 2, 3], [1, 2, 3], [1, 2, 3]];
+
+        let boxes = median_cut_boxes(points, 5);
+
+        assert_eq!(boxes.len(), 1);
+    }
+
+    #[test]
+    fn palette_maps_to_box_average() {
+        let points = vec![
+            [0, 0, 0],
+            [10, 0, 0],
+            [250, 0, 0],
+            [255, 0, 0],
+        ];
+
+        let palette = Palette::from_points(points, 2);
+
+        assert_eq!(palette.colors().len(), 2);
+        assert_eq!(palette.nearest([0, 0, 0]), [5, 0, 0]);
+        assert_eq!(palette.nearest([255, 0, 0]), [252, 0, 0]);
+    }
+}
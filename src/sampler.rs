@@ -15,7 +15,10 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::collections::HashMap;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use super::dither;
 use super::stitch_image::{Image, Color};
 
 struct SampleRange {
@@ -25,6 +28,84 @@ struct SampleRange {
     end_y: u32,
 }
 
+fn average_color(counts: &HashMap<Option<Color>, u32>) -> Option<Color> {
+    let mut sums = [0u64; 3];
+    let mut total = 0u64;
+
+    for (color, &count) in counts {
+        let Some(color) = color else { continue };
+
+        for (sum, &component) in sums.iter_mut().zip(color.iter()) {
+            *sum += component as u64 * count as u64;
+        }
+
+        total += count as u64;
+    }
+
+    if total == 0 {
+        return None;
+    }
+
+    Some([
+        (sums[0] / total) as u8,
+        (sums[1] / total) as u8,
+        (sums[2] / total) as u8,
+    ])
+}
+
+fn palette_squared_distance(adjusted: [f32; 3], candidate: Color) -> f32 {
+    adjusted.iter()
+        .zip(candidate.iter())
+        .map(|(&a, &b)| {
+            let diff = a - b as f32;
+            diff * diff
+        })
+        .sum()
+}
+
+// Builds a Vose alias table for `weights` (assumed to sum to 1) so
+// that a weighted draw over `weights.len()` outcomes can be made in
+// O(1): pick a uniform index `i` and a uniform `u` in [0, 1), then
+// the outcome is `i` if `u < prob[i]` else `alias[i]`.
+fn build_alias_table(weights: &[f64]) -> (Vec<f64>, Vec<usize>) {
+    let n = weights.len();
+    let mut prob = vec![0.0; n];
+    let mut alias = vec![0; n];
+    let mut scaled = weights.iter().map(|&w| w * n as f64).collect::<Vec<_>>();
+
+    let mut small = Vec::new();
+    let mut large = Vec::new();
+
+    for (i, &w) in scaled.iter().enumerate() {
+        if w < 1.0 {
+            small.push(i);
+        } else {
+            large.push(i);
+        }
+    }
+
+    while let (Some(s), Some(l)) = (small.pop(), large.pop()) {
+        prob[s] = scaled[s];
+        alias[s] = l;
+
+        scaled[l] = scaled[l] + scaled[s] - 1.0;
+
+        if scaled[l] < 1.0 {
+            small.push(l);
+        } else {
+            large.push(l);
+        }
+    }
+
+    // Leftover entries only have a scaled weight below or above 1.0
+    // because of floating point error, so they are certain outcomes.
+    for i in large.into_iter().chain(small) {
+        prob[i] = 1.0;
+    }
+
+    (prob, alias)
+}
+
 pub struct Sampler<'a, I: Image> {
     image: &'a I,
     sample_width: f32,
@@ -34,6 +115,39 @@ pub struct Sampler<'a, I: Image> {
     // time the image is sampled. Its contents aren’t reused between
     // sampling.
     counts: Cell<HashMap<Option<Color>, u32>>,
+    // When set, every sample's averaged color is snapped to the nearest
+    // color in this palette and the quantization error is diffused to
+    // neighboring stitches, rather than returning the majority color.
+    dither_palette: Option<Vec<Color>>,
+    // Accumulated, not yet consumed, Floyd–Steinberg error per
+    // stitch, keyed by the current channel (see `set_channel`) and
+    // the same (x, y) coordinates passed to the `sample*` methods.
+    errors: RefCell<HashMap<(usize, u16, u16), [f32; 3]>>,
+    // Which error channel new diffusion is read from and written to.
+    // Callers that sample two disjoint regions through coordinate
+    // spaces that happen to overlap (such as the two mitred halves of
+    // a row sharing the same local x, y numbering) can put each
+    // region on its own channel so error never bleeds between them,
+    // while still diffusing normally from one call to the next within
+    // a channel.
+    channel: Cell<usize>,
+    // Whether the row currently being sampled runs right-to-left,
+    // for boustrophedon (serpentine) dithering, which mirrors which
+    // neighbors are “not yet processed”.
+    row_reversed: Cell<bool>,
+    // When set, a sample window whose fraction of transparent
+    // (`None`) pixels exceeds this threshold collapses to `None`
+    // instead of averaging in the opaque pixels that are left. This
+    // lets an image's alpha channel carve no-stitch holes into the
+    // fabric. Unset, this step is skipped entirely and transparent
+    // pixels are counted like any other color, matching the old
+    // behavior.
+    alpha_threshold: Cell<Option<f32>>,
+    // When set, a cell with more than one candidate color draws one
+    // at random, weighted by its share of the sampled area, instead
+    // of always returning the majority color.
+    stochastic: bool,
+    rng: RefCell<ChaCha8Rng>,
 }
 
 impl<'a, I: Image> Sampler<'a, I> {
@@ -47,9 +161,86 @@ impl<'a, I: Image> Sampler<'a, I> {
             sample_width,
             sample_height,
             counts: Cell::new(HashMap::new()),
+            dither_palette: None,
+            errors: RefCell::new(HashMap::new()),
+            channel: Cell::new(0),
+            row_reversed: Cell::new(false),
+            alpha_threshold: Cell::new(None),
+            stochastic: false,
+            rng: RefCell::new(ChaCha8Rng::seed_from_u64(0)),
+        }
+    }
+
+    // Like `new`, but every sample is dithered onto `palette` using
+    // Floyd–Steinberg error diffusion instead of being returned as a
+    // plain majority-vote color.
+    pub fn new_dithered(
+        image: &'a I,
+        sample_width: f32,
+        sample_height: f32,
+        palette: Vec<Color>,
+    ) -> Sampler<'a, I> {
+        Sampler {
+            image,
+            sample_width,
+            sample_height,
+            counts: Cell::new(HashMap::new()),
+            dither_palette: Some(palette),
+            errors: RefCell::new(HashMap::new()),
+            channel: Cell::new(0),
+            row_reversed: Cell::new(false),
+            alpha_threshold: Cell::new(None),
+            stochastic: false,
+            rng: RefCell::new(ChaCha8Rng::seed_from_u64(0)),
+        }
+    }
+
+    // Like `new`, but a cell with more than one candidate color draws
+    // one at random in proportion to its pixel-area count, using
+    // `seed` so identical inputs yield identical patterns, instead of
+    // collapsing to the single majority color.
+    pub fn new_stochastic(
+        image: &'a I,
+        sample_width: f32,
+        sample_height: f32,
+        seed: u64,
+    ) -> Sampler<'a, I> {
+        Sampler {
+            image,
+            sample_width,
+            sample_height,
+            counts: Cell::new(HashMap::new()),
+            dither_palette: None,
+            errors: RefCell::new(HashMap::new()),
+            channel: Cell::new(0),
+            row_reversed: Cell::new(false),
+            alpha_threshold: Cell::new(None),
+            stochastic: true,
+            rng: RefCell::new(ChaCha8Rng::seed_from_u64(seed)),
         }
     }
 
+    // Flips which neighbors are treated as “not yet processed” when
+    // diffusing error, for boustrophedon traversal. The caller should
+    // set this before sampling each row.
+    pub fn set_row_reversed(&self, reversed: bool) {
+        self.row_reversed.set(reversed);
+    }
+
+    // Switches which error channel subsequent samples diffuse into
+    // and read from. See the `channel` field for why this exists.
+    pub fn set_channel(&self, channel: usize) {
+        self.channel.set(channel);
+    }
+
+    // Sets the fraction of transparent pixels a sample window may
+    // contain before it collapses to `None` instead of averaging in
+    // the opaque pixels that are left. Pass `None` (the default) to
+    // never discard a window this way.
+    pub fn set_alpha_threshold(&self, threshold: Option<f32>) {
+        self.alpha_threshold.set(threshold);
+    }
+
     fn sample_range(&self, x: u16, y: u16, row_height: u16) -> SampleRange {
         SampleRange {
             start_x: (x as f32 * self.sample_width).round() as u32,
@@ -82,6 +273,127 @@ impl<'a, I: Image> Sampler<'a, I> {
         result
     }
 
+    // Like `end_counting`, but for cells whose averaged color should
+    // be snapped onto `self.dither_palette` with the leftover error
+    // diffused to the not-yet-processed neighbors of (x, y). A cell
+    // with no non-background pixels is passed through as `None`
+    // without diffusing any error.
+    fn end_counting_dithered(
+        &self,
+        x: u16,
+        y: u16,
+        counts: HashMap<Option<Color>, u32>,
+        palette: &[Color],
+    ) -> Option<Color> {
+        let average = average_color(&counts);
+
+        self.counts.replace(counts);
+
+        let average = average?;
+
+        let channel = self.channel.get();
+        let mut errors = self.errors.borrow_mut();
+        let accumulated = errors.remove(&(channel, x, y)).unwrap_or([0.0; 3]);
+
+        let adjusted = dither::apply_error(average, accumulated);
+
+        let chosen = *palette.iter()
+            .min_by(|&&a, &&b| {
+                palette_squared_distance(adjusted, a)
+                    .partial_cmp(&palette_squared_distance(adjusted, b))
+                    .unwrap()
+            })
+            .unwrap();
+
+        let error = dither::residual(adjusted, chosen);
+
+        let dx_sign = if self.row_reversed.get() { -1 } else { 1 };
+
+        for &(dx, dy, weight) in dither::WEIGHTS.iter() {
+            let nx = x as i32 + dx * dx_sign;
+            let ny = y as i32 + dy;
+
+            if nx < 0 || ny < 0 {
+                continue;
+            }
+
+            let entry = errors.entry((channel, nx as u16, ny as u16))
+                .or_insert([0.0; 3]);
+
+            for i in 0..3 {
+                entry[i] += error[i] * weight;
+            }
+        }
+
+        Some(chosen)
+    }
+
+    // Like `end_counting`, but instead of always returning the
+    // majority color, draws one of the candidate colors at random
+    // with probability proportional to its share of the sampled
+    // area, using the Vose alias method for an O(1) draw.
+    fn end_counting_stochastic(
+        &self,
+        counts: HashMap<Option<Color>, u32>,
+    ) -> Option<Color> {
+        let mut colors = counts.keys().cloned().collect::<Vec<_>>();
+        // HashMap iteration order differs from one map to the next, so
+        // the candidates are put in a fixed order before drawing.
+        colors.sort_unstable();
+        let total: u32 = colors.iter().map(|color| counts[color]).sum();
+
+        let result = if colors.len() <= 1 || total == 0 {
+            colors.into_iter().next().unwrap_or(None)
+        } else {
+            let weights = colors.iter()
+                .map(|color| counts[color] as f64 / total as f64)
+                .collect::<Vec<_>>();
+            let (prob, alias) = build_alias_table(&weights);
+
+            let mut rng = self.rng.borrow_mut();
+            let i = rng.gen_range(0..colors.len());
+            let u: f64 = rng.gen();
+
+            colors[if u < prob[i] { i } else { alias[i] }].clone()
+        };
+
+        self.counts.replace(counts);
+
+        result
+    }
+
+    fn finish_sample(
+        &self,
+        x: u16,
+        y: u16,
+        mut counts: HashMap<Option<Color>, u32>,
+    ) -> Option<Color> {
+        if let Some(threshold) = self.alpha_threshold.get() {
+            let total: u32 = counts.values().sum();
+            let transparent = counts.get(&None).copied().unwrap_or(0);
+
+            if total > 0 && transparent as f32 / total as f32 > threshold {
+                self.counts.replace(counts);
+                return None;
+            }
+
+            counts.remove(&None);
+        }
+
+        match &self.dither_palette {
+            Some(palette) if !palette.is_empty() => {
+                self.end_counting_dithered(x, y, counts, palette)
+            },
+            _ if self.stochastic => self.end_counting_stochastic(counts),
+            _ if self.alpha_threshold.get().is_some() => {
+                let average = average_color(&counts);
+                self.counts.replace(counts);
+                average
+            },
+            _ => self.end_counting(counts),
+        }
+    }
+
     pub fn sample(
         &self,
         x: u16,
@@ -99,7 +411,7 @@ impl<'a, I: Image> Sampler<'a, I> {
             }
         }
 
-        self.end_counting(counts)
+        self.finish_sample(x, y, counts)
     }
 
     pub fn sample_lower_left_triangle(&self, x: u16, y: u16) -> Option<Color> {
@@ -125,7 +437,7 @@ impl<'a, I: Image> Sampler<'a, I> {
             }
         }
 
-        self.end_counting(counts)
+        self.finish_sample(x, y, counts)
     }
 
     pub fn sample_upper_right_triangle(&self, x: u16, y: u16) -> Option<Color> {
@@ -151,7 +463,7 @@ impl<'a, I: Image> Sampler<'a, I> {
             }
         }
 
-        self.end_counting(counts)
+        self.finish_sample(x, y, counts)
     }
 }
 
@@ -234,4 +546,185 @@ mod test {
             Some([0, 255, 0]),
         );
     }
+
+    #[test]
+    fn dithered_sample_snaps_to_palette() {
+        let image = FakeImage { };
+        let palette = vec![[255, 0, 0], [0, 255, 0]];
+        let sampler = Sampler::new_dithered(&image, 4.0, 4.0, palette);
+
+        // Cell (0, 0) straddles the diagonal and averages to
+        // [159, 95, 0], which snaps to the red entry. Cell (1, 0) is
+        // solid green, and the error diffused into it from (0, 0) is
+        // clamped away, so it snaps to the green entry exactly.
+        assert_eq!(sampler.sample(0, 0, 1), Some([255, 0, 0]));
+        assert_eq!(sampler.sample(1, 0, 1), Some([0, 255, 0]));
+    }
+
+    #[test]
+    fn dithered_sample_passes_through_none() {
+        struct TransparentImage;
+
+        impl Image for TransparentImage {
+            fn width(&self) -> u32 {
+                4
+            }
+
+            fn height(&self) -> u32 {
+                4
+            }
+
+            fn get_pixel(&self, _x: u32, _y: u32) -> Option<Color> {
+                None
+            }
+        }
+
+        let image = TransparentImage;
+        let palette = vec![[255, 0, 0], [0, 255, 0]];
+        let sampler = Sampler::new_dithered(&image, 4.0, 4.0, palette);
+
+        assert_eq!(sampler.sample(0, 0, 1), None);
+    }
+
+    struct DitherGradientImage;
+
+    impl Image for DitherGradientImage {
+        fn width(&self) -> u32 {
+            4
+        }
+
+        fn height(&self) -> u32 {
+            1
+        }
+
+        fn get_pixel(&self, x: u32, _y: u32) -> Option<Color> {
+            const VALUES: [u8; 4] = [0, 150, 150, 150];
+
+            Some([VALUES[x as usize], 0, 0])
+        }
+    }
+
+    #[test]
+    fn channels_keep_diffused_error_independent() {
+        let image = DitherGradientImage;
+        let palette = vec![[0, 0, 0], [255, 0, 0]];
+        let sampler = Sampler::new_dithered(&image, 1.0, 1.0, palette);
+
+        let channel_0 = (0..4)
+            .map(|x| sampler.sample(x, 0, 1))
+            .collect::<Vec<_>>();
+
+        // Re-running over the same (x, y) coordinates on a fresh
+        // channel must reproduce exactly the same dithering, rather
+        // than inheriting leftover error from channel 0.
+        sampler.set_channel(1);
+
+        let channel_1 = (0..4)
+            .map(|x| sampler.sample(x, 0, 1))
+            .collect::<Vec<_>>();
+
+        assert_eq!(channel_0, channel_1);
+    }
+
+    #[test]
+    fn stochastic_sample_picks_a_candidate_color() {
+        let image = FakeImage { };
+        let sampler = Sampler::new_stochastic(&image, 4.0, 4.0, 42);
+
+        // The cell straddling the diagonal mixes the two colors, but
+        // whichever is picked must be one of the colors actually
+        // present in the sample window.
+        let color = sampler.sample(2, 2, 1);
+        assert!(color == Some([255, 0, 0]) || color == Some([0, 255, 0]));
+    }
+
+    #[test]
+    fn stochastic_sample_is_reproducible() {
+        let image = FakeImage { };
+        let sampler_a = Sampler::new_stochastic(&image, 4.0, 4.0, 7);
+        let sampler_b = Sampler::new_stochastic(&image, 4.0, 4.0, 7);
+
+        assert_eq!(sampler_a.sample(2, 2, 1), sampler_b.sample(2, 2, 1));
+    }
+
+    #[test]
+    fn stochastic_sample_with_one_candidate_is_certain() {
+        let image = FakeImage { };
+        let sampler = Sampler::new_stochastic(&image, 4.0, 4.0, 0);
+
+        // This cell is entirely the second color, so there is only
+        // one candidate and the result is certain regardless of seed.
+        assert_eq!(sampler.sample(1, 0, 1), Some([0, 255, 0]));
+    }
+
+    // A 2×2 image that is half opaque red and half transparent, so a
+    // single 2×2 sample window straddles both.
+    struct HalfTransparentImage;
+
+    impl Image for HalfTransparentImage {
+        fn width(&self) -> u32 {
+            2
+        }
+
+        fn height(&self) -> u32 {
+            2
+        }
+
+        fn get_pixel(&self, x: u32, _y: u32) -> Option<Color> {
+            if x == 0 {
+                Some([255, 0, 0])
+            } else {
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_threshold_is_ignored_by_default() {
+        // Two opaque red pixels and one transparent one. With no
+        // threshold the transparent pixel is only one vote of three,
+        // whereas a threshold of 0.25 would discard the window.
+        struct MostlyOpaqueImage;
+
+        impl Image for MostlyOpaqueImage {
+            fn width(&self) -> u32 {
+                3
+            }
+
+            fn height(&self) -> u32 {
+                1
+            }
+
+            fn get_pixel(&self, x: u32, _y: u32) -> Option<Color> {
+                if x < 2 {
+                    Some([255, 0, 0])
+                } else {
+                    None
+                }
+            }
+        }
+
+        let image = MostlyOpaqueImage;
+        let sampler = Sampler::new(&image, 3.0, 1.0);
+
+        assert_eq!(sampler.sample(0, 0, 1), Some([255, 0, 0]));
+    }
+
+    #[test]
+    fn sample_below_alpha_threshold_averages_opaque_pixels_only() {
+        let image = HalfTransparentImage;
+        let sampler = Sampler::new(&image, 2.0, 2.0);
+        sampler.set_alpha_threshold(Some(0.5));
+
+        assert_eq!(sampler.sample(0, 0, 1), Some([255, 0, 0]));
+    }
+
+    #[test]
+    fn sample_above_alpha_threshold_becomes_none() {
+        let image = HalfTransparentImage;
+        let sampler = Sampler::new(&image, 2.0, 2.0);
+        sampler.set_alpha_threshold(Some(0.25));
+
+        assert_eq!(sampler.sample(0, 0, 1), None);
+    }
 }
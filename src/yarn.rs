@@ -0,0 +1,202 @@
+// Stichify – A utility to generate intarsia knitting patterns
+// Copyright (C) 2025  Neil Roberts
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::str::FromStr;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct YarnColor {
+    pub color: [u8; 3],
+    pub name: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum YarnColorParseError {
+    MissingElement,
+    ParseIntError(std::num::ParseIntError),
+}
+
+impl From<std::num::ParseIntError> for YarnColorParseError {
+    fn from(e: std::num::ParseIntError) -> YarnColorParseError {
+        YarnColorParseError::ParseIntError(e)
+    }
+}
+
+impl std::error::Error for YarnColorParseError {
+}
+
+impl fmt::Display for YarnColorParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            YarnColorParseError::ParseIntError(e) => write!(f, "{}", e),
+            YarnColorParseError::MissingElement => {
+                write!(f, "Yarn argument must be of the form “r,g,b[,name]”")
+            },
+        }
+    }
+}
+
+impl FromStr for YarnColor {
+    type Err = YarnColorParseError;
+
+    fn from_str(s: &str) -> Result<YarnColor, YarnColorParseError> {
+        let mut parts = s.splitn(4, ',');
+
+        let r = parts.next()
+            .ok_or(YarnColorParseError::MissingElement)?
+            .parse::<u8>()?;
+        let g = parts.next()
+            .ok_or(YarnColorParseError::MissingElement)?
+            .parse::<u8>()?;
+        let b = parts.next()
+            .ok_or(YarnColorParseError::MissingElement)?
+            .parse::<u8>()?;
+        let name = parts.next().map(str::to_string);
+
+        Ok(YarnColor { color: [r, g, b], name })
+    }
+}
+
+// Converts an 8-bit sRGB color to CIE L*a*b*, using a D65 reference
+// white, so that color distances can be compared perceptually instead
+// of in raw RGB, which badly misjudges greens and blues.
+fn to_lab(color: [u8; 3]) -> [f32; 3] {
+    fn to_linear(component: u8) -> f32 {
+        let c = component as f32 / 255.0;
+
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    }
+
+    let r = to_linear(color[0]);
+    let g = to_linear(color[1]);
+    let b = to_linear(color[2]);
+
+    let x = r * 0.4124564 + g * 0.3575761 + b * 0.1804375;
+    let y = r * 0.2126729 + g * 0.7151522 + b * 0.0721750;
+    let z = r * 0.0193339 + g * 0.1191920 + b * 0.9503041;
+
+    const WHITE_X: f32 = 0.95047;
+    const WHITE_Y: f32 = 1.0;
+    const WHITE_Z: f32 = 1.08883;
+
+    fn f(t: f32) -> f32 {
+        const DELTA: f32 = 6.0 / 29.0;
+
+        if t > DELTA * DELTA * DELTA {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    }
+
+    let fx = f(x / WHITE_X);
+    let fy = f(y / WHITE_Y);
+    let fz = f(z / WHITE_Z);
+
+    [
+        116.0 * fy - 16.0,
+        500.0 * (fx - fy),
+        200.0 * (fy - fz),
+    ]
+}
+
+fn delta_e_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(&a, &b)| {
+            let diff = a - b;
+            diff * diff
+        })
+        .sum()
+}
+
+// A fixed set of yarn colors that stitch colors can be snapped onto,
+// using ΔE (CIELAB Euclidean distance) to find the closest match.
+pub struct YarnPalette {
+    colors: Vec<YarnColor>,
+    lab: Vec<[f32; 3]>,
+}
+
+impl YarnPalette {
+    pub fn new(colors: Vec<YarnColor>) -> YarnPalette {
+        let lab = colors.iter().map(|yarn| to_lab(yarn.color)).collect();
+
+        YarnPalette { colors, lab }
+    }
+
+    pub fn nearest(&self, color: [u8; 3]) -> &YarnColor {
+        let target = to_lab(color);
+
+        let index = (0..self.lab.len())
+            .min_by(|&a, &b| {
+                delta_e_squared(target, self.lab[a])
+                    .partial_cmp(&delta_e_squared(target, self.lab[b]))
+                    .unwrap()
+            })
+            .unwrap();
+
+        &self.colors[index]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_color_without_name() {
+        let yarn = "255,0,128".parse::<YarnColor>().unwrap();
+
+        assert_eq!(yarn.color, [255, 0, 128]);
+        assert_eq!(yarn.name, None);
+    }
+
+    #[test]
+    fn parses_color_with_name() {
+        let yarn = "255,0,128,Hot Pink".parse::<YarnColor>().unwrap();
+
+        assert_eq!(yarn.color, [255, 0, 128]);
+        assert_eq!(yarn.name, Some("Hot Pink".to_string()));
+    }
+
+    #[test]
+    fn rejects_missing_elements() {
+        assert!("255,0".parse::<YarnColor>().is_err());
+    }
+
+    #[test]
+    fn nearest_prefers_perceptual_distance() {
+        // In raw RGB, [0, 160, 0] is closer to [0, 0, 0] (squared
+        // distance 25600) than to [200, 200, 0] (41600), but
+        // perceptually this green is much brighter than that RGB
+        // distance suggests, so ΔE picks the yellow.
+        let palette = YarnPalette::new(vec![
+            YarnColor { color: [0, 0, 0], name: Some("Black".to_string()) },
+            YarnColor {
+                color: [200, 200, 0],
+                name: Some("Yellow".to_string()),
+            },
+        ]);
+
+        let nearest = palette.nearest([0, 160, 0]);
+
+        assert_eq!(nearest.name, Some("Yellow".to_string()));
+    }
+}